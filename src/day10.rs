@@ -1,6 +1,8 @@
 use ahash::AHashSet;
 use eyre::{eyre, Error};
+use itertools::Itertools;
 use num::Integer;
+use std::fmt::Write;
 use std::str::FromStr;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -57,6 +59,7 @@ impl Tile {
         }
     }
 }
+#[derive(Debug)]
 struct Map {
     ground: Vec<Vec<Tile>>,
     hlen: usize,
@@ -78,7 +81,7 @@ impl FromStr for Map {
         let mut ground = ground?;
         let hlen = ground[0].len();
         let vlen = ground.len();
-        let start = ground
+        let mut starts = ground
             .iter()
             .enumerate()
             .flat_map(|(j, l)| l.iter().enumerate().map(move |(i, t)| (t, i, j)))
@@ -88,11 +91,14 @@ impl FromStr for Map {
                 } else {
                     None
                 }
-            })
-            .next()
-            .ok_or(eyre!("didn't found start !!"))?;
+            });
+        let start = starts.next().ok_or(eyre!("didn't found start !!"))?;
+
+        if starts.next().is_some() {
+            return Err(eyre!("several starts found"));
+        }
 
-        ground[start.1][start.0] = Map::get_start_type(start, &ground);
+        ground[start.1][start.0] = Map::get_start_type(start, &ground)?;
         Ok(Self {
             ground,
             hlen,
@@ -102,6 +108,14 @@ impl FromStr for Map {
     }
 }
 
+#[derive(Debug, Default)]
+struct PipesAnalysis {
+    /// closed loops, each one in order
+    loops: Vec<Vec<(usize, usize)>>,
+    dangling: Vec<(usize, usize)>,
+    unconnected: Vec<(usize, usize)>,
+}
+
 impl Map {
     fn are_connected(t1: Tile, pos1: (usize, usize), t2: Tile, pos2: (usize, usize)) -> bool {
         let (x1, y1) = pos1;
@@ -147,49 +161,88 @@ impl Map {
         result
     }
     fn get_connected_neighbours(&self, pos: (usize, usize)) -> Vec<(Tile, (usize, usize))> {
+        Self::connected_neighbours(pos, &self.ground)
+    }
+
+    fn connected_neighbours(
+        pos: (usize, usize),
+        ground: &[Vec<Tile>],
+    ) -> Vec<(Tile, (usize, usize))> {
         let (x, y) = pos;
-        let current = &self.ground[y][x];
-        Self::get_neighbours(x, y, &self.ground)
+        let current = &ground[y][x];
+        Self::get_neighbours(x, y, ground)
             .iter()
             .filter(|(tn, posn)| Map::are_connected(*current, pos, *tn, *posn))
             .copied()
             .collect()
     }
-    fn get_start_type(start_pos: (usize, usize), ground: &[Vec<Tile>]) -> Tile {
+
+    /// follows the pipes from pos : it is a loop if every tile met is connected on both ends,
+    /// until we're back to pos
+    fn trace_loop(pos: (usize, usize), ground: &[Vec<Tile>]) -> Option<Vec<(usize, usize)>> {
+        let mut path = vec![pos];
+        let mut previous = pos;
+        let mut current = match Self::connected_neighbours(pos, ground)[..] {
+            [(_, first), _] => first,
+            _ => return None,
+        };
+        while current != pos {
+            path.push(current);
+            let next = match Self::connected_neighbours(current, ground)[..] {
+                [(_, n1), (_, n2)] => {
+                    if n1 == previous {
+                        n2
+                    } else {
+                        n1
+                    }
+                }
+                _ => return None,
+            };
+            previous = current;
+            current = next;
+        }
+        Some(path)
+    }
+    /// start tile must link 2 of its neighbours, and be the only choice
+    /// closing a loop through start
+    fn get_start_type(start_pos: (usize, usize), ground: &[Vec<Tile>]) -> Result<Tile, Error> {
         let (startx, starty) = start_pos;
         let neighbours = Map::get_neighbours(startx, starty, ground);
-        let north_n = neighbours.iter().find(|(t, (_, y))| t.can_face_south() && *y < starty);
-        let south_n = neighbours.iter().find(|(t, (_, y))| t.can_face_north() && *y > starty);
-        let east_n = neighbours.iter().find(|(t, (x, _))| t.can_face_west() && *x > startx);
-        let west_n = neighbours.iter().find(|(t, (x, _))| t.can_face_east() && *x < startx);
-
-        if north_n.is_some() {
-            if south_n.is_some() {
-                return Tile::Vert;
-            }
-            if east_n.is_some() {
-                return Tile::NorthEast;
-            }
-            if west_n.is_some() {
-                return Tile::NorthWest;
-            }
-            unreachable!();
+        let north_n = neighbours.iter().any(|(t, (_, y))| t.can_face_south() && *y < starty);
+        let south_n = neighbours.iter().any(|(t, (_, y))| t.can_face_north() && *y > starty);
+        let east_n = neighbours.iter().any(|(t, (x, _))| t.can_face_west() && *x > startx);
+        let west_n = neighbours.iter().any(|(t, (x, _))| t.can_face_east() && *x < startx);
+
+        let candidates: Vec<Tile> = [
+            (north_n && south_n, Tile::Vert),
+            (north_n && east_n, Tile::NorthEast),
+            (north_n && west_n, Tile::NorthWest),
+            (south_n && east_n, Tile::SouthEast),
+            (south_n && west_n, Tile::SouthWest),
+            (west_n && east_n, Tile::Hrz),
+        ]
+        .into_iter()
+        .filter_map(|(possible, tile)| possible.then_some(tile))
+        .collect();
+
+        if candidates.is_empty() {
+            return Err(eyre!("start {start_pos:?} does not connect 2 pipes"));
         }
-        if south_n.is_some() {
-            if east_n.is_some() {
-                return Tile::SouthEast;
-            }
-            if west_n.is_some() {
-                return Tile::SouthWest;
-            }
-            unreachable!()
-        }
-
-        if west_n.and(east_n).is_some() {
-            return Tile::Hrz;
+        let closing: Vec<Tile> = candidates
+            .into_iter()
+            .filter(|tile| {
+                let mut ground = ground.to_vec();
+                ground[starty][startx] = *tile;
+                Map::trace_loop(start_pos, &ground).is_some()
+            })
+            .collect();
+        match closing[..] {
+            [tile] => Ok(tile),
+            [] => Err(eyre!("start {start_pos:?} is not on a loop")),
+            _ => Err(eyre!(
+                "start {start_pos:?} is ambiguous, could be any of {closing:?}"
+            )),
         }
-
-        unreachable!()
     }
 
     pub fn get_loop(&self) -> (AHashSet<(usize, usize)>, u64) {
@@ -328,14 +381,161 @@ impl Map {
 
         area
     }
+
+    /// loop positions, in order, from start
+    pub fn loop_path(&self) -> Vec<(usize, usize)> {
+        Self::trace_loop(self.start, &self.ground).expect("start tile type is checked at init")
+    }
+
+    /// shoelace formula gives the area of the polygon linking tiles centers,
+    /// then Pick's theorem (A = i + b/2 - 1) gives the inner tiles count
+    fn enclosed_area(path: &[(usize, usize)]) -> u64 {
+        let double_area: i64 = path
+            .iter()
+            .chain(path.first())
+            .tuple_windows()
+            .map(|((x1, y1), (x2, y2))| (*x1 as i64 * *y2 as i64) - (*x2 as i64 * *y1 as i64))
+            .sum();
+
+        (double_area.unsigned_abs() + 2 - path.len() as u64) / 2
+    }
+
+    fn shoelace_area(&self) -> u64 {
+        Self::enclosed_area(&self.loop_path())
+    }
+
+    /// every pipe tile ends up either in a closed loop, dangling (connected to other pipes but
+    /// not closing a loop), or unconnected
+    fn analyse_pipes(&self) -> PipesAnalysis {
+        let mut analysis = PipesAnalysis::default();
+        let mut seen: AHashSet<(usize, usize)> = AHashSet::new();
+        for y in 0..self.vlen {
+            for x in 0..self.hlen {
+                if self.ground[y][x] == Tile::Ground || seen.contains(&(x, y)) {
+                    continue;
+                }
+                match Self::trace_loop((x, y), &self.ground) {
+                    Some(path) => {
+                        seen.extend(path.iter().copied());
+                        analysis.loops.push(path);
+                    }
+                    None if self.get_connected_neighbours((x, y)).is_empty() => {
+                        seen.insert((x, y));
+                        analysis.unconnected.push((x, y));
+                    }
+                    None => {
+                        seen.insert((x, y));
+                        analysis.dangling.push((x, y));
+                    }
+                }
+            }
+        }
+        analysis
+    }
+
+    /// even-odd scan : each loop tile facing north is crossed when going east
+    fn inside_tiles(&self) -> Vec<Vec<bool>> {
+        let pipe_loop: AHashSet<(usize, usize)> = self.loop_path().into_iter().collect();
+        (0..self.vlen)
+            .map(|y| {
+                let mut inside = false;
+                (0..self.hlen)
+                    .map(|x| {
+                        if pipe_loop.contains(&(x, y)) {
+                            if self.ground[y][x].can_face_north() {
+                                inside = !inside;
+                            }
+                            false
+                        } else {
+                            inside
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// pipes are drawn through tiles centers, the loop in black,
+    /// while tiles out of the loop are filled green inside and grey outside
+    fn to_svg(&self) -> String {
+        const SIZE: usize = 10;
+        const HALF: usize = SIZE / 2;
+        let inside = self.inside_tiles();
+        let path = self.loop_path();
+        let pipe_loop: AHashSet<(usize, usize)> = path.iter().copied().collect();
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n",
+            self.hlen * SIZE,
+            self.vlen * SIZE
+        );
+        for (y, row) in inside.iter().enumerate() {
+            for (x, inside) in row.iter().enumerate() {
+                if pipe_loop.contains(&(x, y)) {
+                    continue;
+                }
+                let color = if *inside { "green" } else { "lightgrey" };
+                let _ = writeln!(
+                    svg,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{SIZE}\" height=\"{SIZE}\" fill=\"{color}\"/>",
+                    x * SIZE,
+                    y * SIZE
+                );
+            }
+        }
+        // dangling pipes
+        for (y, row) in self.ground.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                if pipe_loop.contains(&(x, y)) {
+                    continue;
+                }
+                let (cx, cy) = (x * SIZE + HALF, y * SIZE + HALF);
+                for (facing, (dx, dy)) in [
+                    (tile.can_face_north(), (cx, y * SIZE)),
+                    (tile.can_face_south(), (cx, (y + 1) * SIZE)),
+                    (tile.can_face_east(), ((x + 1) * SIZE, cy)),
+                    (tile.can_face_west(), (x * SIZE, cy)),
+                ] {
+                    if facing {
+                        let _ = writeln!(
+                            svg,
+                            "<line x1=\"{cx}\" y1=\"{cy}\" x2=\"{dx}\" y2=\"{dy}\" stroke=\"grey\"/>"
+                        );
+                    }
+                }
+            }
+        }
+        let points =
+            path.iter().map(|(x, y)| format!("{},{}", x * SIZE + HALF, y * SIZE + HALF)).join(" ");
+        let _ = writeln!(
+            svg,
+            "<polygon points=\"{points}\" fill=\"none\" stroke=\"black\" stroke-width=\"2\"/>"
+        );
+        svg.push_str("</svg>\n");
+        svg
+    }
 }
 
-pub fn follow_pipes() {
+pub fn follow_pipes(export: bool) {
     let map: Map = include_str!("../resources/day10_pipes.txt").parse().unwrap();
     let farthest = map.get_loop().1;
     println!("fartest : {farthest}");
     let area = map.inner_area();
     println!("loop area : {area}");
+    let shoelace_area = map.shoelace_area();
+    println!("loop area (shoelace) : {shoelace_area}");
+    let analysis = map.analyse_pipes();
+    let areas = analysis.loops.iter().map(|l| Map::enclosed_area(l)).collect_vec();
+    println!("loops enclosed areas : {areas:?}");
+    println!(
+        "dangling pipes : {}, unconnected pipes : {}",
+        analysis.dangling.len(),
+        analysis.unconnected.len()
+    );
+    if export {
+        std::fs::write(".day10_pipes.svg", map.to_svg()).unwrap();
+        println!("pipes drawn to .day10_pipes.svg");
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -409,5 +609,124 @@ mod tests {
         .parse()
         .unwrap();
         assert_eq!(10, map.inner_area());
+        assert_eq!(10, map.shoelace_area());
+    }
+
+    #[test]
+    fn loop_path_works() {
+        let map: Map = indoc! {"
+            ..F7.
+            .FJ|.
+            SJ.L7
+            |F--J
+            LJ...
+        "}
+        .parse()
+        .unwrap();
+        let path = map.loop_path();
+        assert_eq!(16, path.len());
+        assert_eq!((0, 2), path[0]);
+        for (p1, p2) in path.iter().chain(path.first()).tuple_windows() {
+            assert_eq!(1, p1.0.abs_diff(p2.0) + p1.1.abs_diff(p2.1));
+        }
+        assert_eq!(1, map.shoelace_area());
+
+        let map: Map = indoc! {"
+            ...........
+            .S-------7.
+            .|F-----7|.
+            .||.....||.
+            .||.....||.
+            .|L-7.F-J|.
+            .|..|.|..|.
+            .L--J.L--J.
+            ...........
+        "}
+        .parse()
+        .unwrap();
+        assert_eq!(4, map.shoelace_area());
+        let inside = map.inside_tiles();
+        assert_eq!(4, inside.iter().flatten().filter(|i| **i).count());
+        assert!(inside[6][2] && !inside[3][3]);
+
+        let svg = map.to_svg();
+        assert!(svg
+            .starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"110\" height=\"90\">"));
+        assert_eq!(4, svg.matches("fill=\"green\"").count());
+        assert!(svg.contains("<polygon points=\"15,15 25,15 "));
+    }
+
+    #[test]
+    fn pipes_analysis_works() {
+        let map: Map = indoc! {"
+            -L|F7
+            7S-7|
+            L|7||
+            -L-J|
+            L|-JF
+        "}
+        .parse()
+        .unwrap();
+        let analysis = map.analyse_pipes();
+        assert_eq!(1, analysis.loops.len());
+        assert_eq!(8, analysis.loops[0].len());
+        assert_eq!(
+            vec![
+                (0, 0),
+                (1, 0),
+                (2, 0),
+                (2, 2),
+                (0, 3),
+                (0, 4),
+                (1, 4),
+                (4, 4)
+            ],
+            analysis.unconnected
+        );
+        assert_eq!(9, analysis.dangling.len());
+
+        // a second loop, not going through start
+        let map: Map = indoc! {"
+            S7.F-7
+            LJ.|.|
+            ...L-J
+        "}
+        .parse()
+        .unwrap();
+        let analysis = map.analyse_pipes();
+        assert_eq!(2, analysis.loops.len());
+        assert_eq!(
+            vec![0, 1],
+            analysis.loops.iter().map(|l| Map::enclosed_area(l)).collect_vec()
+        );
+        assert!(analysis.dangling.is_empty() && analysis.unconnected.is_empty());
+
+        // start would close a loop either way
+        let ambiguous: Result<Map, _> = indoc! {"
+            F-7..
+            |.|..
+            L-S-7
+            ..|.|
+            ..L-J
+        "}
+        .parse();
+        assert!(ambiguous.unwrap_err().to_string().contains("ambiguous"));
+        // a single candidate, but its pipes lead nowhere
+        let open: Result<Map, _> = "S-.\n|..\n".parse();
+        assert!(open.unwrap_err().to_string().contains("is not on a loop"));
+        // only a vertical start closes a loop
+        let map: Map = indoc! {"
+            F--7
+            S-.|
+            |..|
+            L--J
+        "}
+        .parse()
+        .unwrap();
+        assert_eq!(Tile::Vert, map.ground[1][0]);
+        assert_eq!(vec![(1, 1)], map.analyse_pipes().unconnected);
+
+        assert!("..\n.S\n".parse::<Map>().is_err());
+        assert!("S-S\n".parse::<Map>().is_err());
     }
 }
@@ -1,67 +1,133 @@
-use ahash::AHashSet;
 use itertools::Itertools;
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
-struct Sky<const F: usize> {
-    galaxies: AHashSet<(usize, usize)>,
+type Galaxy = (u128, u128);
+
+/// galaxies are read unexpanded, see `Sky::expand`
+struct Sky {
+    galaxies: Vec<Galaxy>,
 }
-impl<const F: usize> FromStr for Sky<F> {
+impl FromStr for Sky {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut galaxies: AHashSet<_> = s
+        let galaxies = s
             .lines()
             .filter(|l| !l.is_empty())
             .enumerate()
             .flat_map(|(j, l)| {
-                l.trim().chars().enumerate().filter_map(move |(i, c)| (c == '#').then_some((i, j)))
+                l.trim()
+                    .chars()
+                    .enumerate()
+                    .filter_map(move |(i, c)| (c == '#').then_some((i as u128, j as u128)))
             })
             .collect();
 
-        let maxx = galaxies.iter().map(|(x, _)| *x).max().unwrap_or(0);
-        let maxy = galaxies.iter().map(|(_, y)| *y).max().unwrap_or(0);
-        for x in (1..maxx).rev() {
-            if !galaxies.iter().any(|(i, _)| *i == x) {
-                galaxies = galaxies
-                    .into_iter()
-                    .map(|(i, j)| if i > x { (i + F - 1, j) } else { (i, j) })
-                    .collect()
-            }
-        }
-
-        for y in (1..maxy).rev() {
-            if !galaxies.iter().any(|(_, j)| *j == y) {
-                galaxies = galaxies
-                    .into_iter()
-                    .map(|(i, j)| if j > y { (i, j + F - 1) } else { (i, j) })
-                    .collect()
-            }
-        }
         Ok(Self { galaxies })
     }
 }
 
-fn distance(g1: (usize, usize), g2: (usize, usize)) -> usize {
+/// each empty line before a coordinate becomes factor lines : 0 removes empty lines
+fn expand_axis(coords: &[u128], factor: u128) -> Vec<u128> {
+    let occupied = coords.iter().copied().sorted().dedup().collect_vec();
+    coords
+        .iter()
+        .map(|c| {
+            let empty_before = c - occupied.partition_point(|o| o < c) as u128;
+            c - empty_before + empty_before * factor
+        })
+        .collect()
+}
+
+/// sum of |c_i - c_j| for all i < j : once sorted, c_i is added i times and subtracted n-1-i times
+fn sum_axis_distance(coords: impl Iterator<Item = u128>) -> u128 {
+    coords
+        .sorted()
+        .enumerate()
+        .fold((0, 0), |(sum, prefix), (i, c)| {
+            (sum + c * i as u128 - prefix, prefix + c)
+        })
+        .0
+}
+
+fn distance(g1: Galaxy, g2: Galaxy) -> u128 {
     let (x1, y1) = g1;
     let (x2, y2) = g2;
 
     x1.abs_diff(x2) + y1.abs_diff(y2)
 }
-impl<const N: usize> Sky<N> {
-    pub fn sum_distance(&self) -> usize {
-        self.galaxies.iter().combinations(2).map(|g| distance(*g[0], *g[1])).sum()
+impl Sky {
+    pub fn expand(&self, factor: u128) -> Self {
+        let xs = expand_axis(&self.galaxies.iter().map(|(x, _)| *x).collect_vec(), factor);
+        let ys = expand_axis(&self.galaxies.iter().map(|(_, y)| *y).collect_vec(), factor);
+        Self {
+            galaxies: xs.into_iter().zip(ys).collect(),
+        }
+    }
+
+    pub fn sum_distance(&self) -> u128 {
+        sum_axis_distance(self.galaxies.iter().map(|(x, _)| *x))
+            + sum_axis_distance(self.galaxies.iter().map(|(_, y)| *y))
+    }
+
+    /// galaxies sorted by x : once x distance alone exceeds the best distance, no need to look further
+    pub fn nearest_pair(&self) -> Option<(Galaxy, Galaxy, u128)> {
+        let sorted = self.galaxies.iter().copied().sorted().collect_vec();
+        let mut nearest: Option<(Galaxy, Galaxy, u128)> = None;
+        for (i, g1) in sorted.iter().enumerate() {
+            for g2 in &sorted[i + 1..] {
+                let best = nearest.map(|(_, _, d)| d).unwrap_or(u128::MAX);
+                if g2.0 - g1.0 >= best {
+                    break;
+                }
+                if distance(*g1, *g2) < best {
+                    nearest = Some((*g1, *g2, distance(*g1, *g2)));
+                }
+            }
+        }
+        nearest
+    }
+
+    /// the farthest pair maximises either |(x1+y1) - (x2+y2)| or |(x1-y1) - (x2-y2)|
+    pub fn farthest_pair(&self) -> Option<(Galaxy, Galaxy, u128)> {
+        let sum = |g: &&Galaxy| g.0 + g.1;
+        let diff = |g: &&Galaxy| g.0 as i128 - g.1 as i128;
+        let (min_sum, max_sum) = self.galaxies.iter().minmax_by_key(sum).into_option()?;
+        let (min_diff, max_diff) = self.galaxies.iter().minmax_by_key(diff).into_option()?;
+        [(*min_sum, *max_sum), (*min_diff, *max_diff)]
+            .into_iter()
+            .map(|(g1, g2)| (g1, g2, distance(g1, g2)))
+            .max_by_key(|(_, _, d)| *d)
+    }
+
+    /// count of galaxy pairs by distance, grouped in buckets of bucket_size
+    pub fn distance_histogram(&self, bucket_size: u128) -> BTreeMap<u128, usize> {
+        self.galaxies
+            .iter()
+            .tuple_combinations()
+            .map(|(g1, g2)| distance(*g1, *g2) / bucket_size * bucket_size)
+            .counts()
+            .into_iter()
+            .collect()
     }
 }
 
 pub fn observe_space() {
-    let sky: Sky<1> = include_str!("../resources/day11_space_observation.txt").parse().unwrap();
-    let sum_distance = sky.sum_distance();
+    let sky: Sky = include_str!("../resources/day11_space_observation.txt").parse().unwrap();
+    let sum_distance = sky.expand(1).sum_distance();
     println!("sum distance {sum_distance}");
 
-    let sky1000000: Sky<1000000> =
-        include_str!("../resources/day11_space_observation.txt").parse().unwrap();
+    let sky1000000 = sky.expand(1000000);
     let sum_distance1000000 = sky1000000.sum_distance();
     println!("sum distance1000000 {sum_distance1000000}");
+
+    let nearest = sky1000000.nearest_pair().unwrap();
+    println!("nearest galaxies (expanded 1000000) {nearest:?}");
+    let farthest = sky1000000.farthest_pair().unwrap();
+    println!("farthest galaxies (expanded 1000000) {farthest:?}");
+    let histogram = sky.expand(2).distance_histogram(50);
+    println!("distance histogram (expanded 2) {histogram:?}");
 }
 #[cfg(test)]
 mod tests {
@@ -81,14 +147,63 @@ mod tests {
             .......#..
             #...#.....
         "};
-        let sky: Sky<2> = input.parse().unwrap();
+        let sky: Sky = input.parse().unwrap();
+
+        assert_eq!(374, sky.expand(2).sum_distance());
+
+        assert_eq!(1030, sky.expand(10).sum_distance());
+
+        assert_eq!(8410, sky.expand(100).sum_distance());
+    }
+
+    #[test]
+    fn sky_queries_works() {
+        let input = indoc! {"
+            ...#......
+            .......#..
+            #.........
+            ..........
+            ......#...
+            .#........
+            .........#
+            ..........
+            .......#..
+            #...#.....
+        "};
+        let sky = input.parse::<Sky>().unwrap().expand(2);
+        let brute_force: u128 =
+            sky.galaxies.iter().tuple_combinations().map(|(g1, g2)| distance(*g1, *g2)).sum();
+        assert_eq!(brute_force, sky.sum_distance());
+
+        let (g1, g2, nearest) = sky.nearest_pair().unwrap();
+        assert_eq!((5, 5), (nearest, distance(g1, g2)));
+        assert!(sky.galaxies.iter().tuple_combinations().all(|(g1, g2)| distance(*g1, *g2) >= 5));
+        let farthest = sky.galaxies.iter().tuple_combinations().map(|(g1, g2)| distance(*g1, *g2));
+        assert_eq!(farthest.max(), sky.farthest_pair().map(|(_, _, d)| d));
+        assert_eq!(19, sky.farthest_pair().unwrap().2);
 
-        assert_eq!(374, sky.sum_distance());
+        let histogram = sky.distance_histogram(5);
+        assert_eq!(36, histogram.values().sum::<usize>());
+        assert_eq!(
+            (Some(&5), Some(&15)),
+            (histogram.keys().next(), histogram.keys().last())
+        );
 
-        let sky10: Sky<10> = input.parse().unwrap();
-        assert_eq!(1030, sky10.sum_distance());
+        // empty lines removed : 7 occupied columns and 8 occupied rows left
+        let compact = input.parse::<Sky>().unwrap().expand(0);
+        assert_eq!(
+            (6, 7),
+            (
+                compact.galaxies.iter().map(|(x, _)| *x).max().unwrap(),
+                compact.galaxies.iter().map(|(_, y)| *y).max().unwrap()
+            )
+        );
 
-        let sky100: Sky<100> = input.parse().unwrap();
-        assert_eq!(8410, sky100.sum_distance());
+        // 10^30 expansion would overflow u64
+        let huge = input.parse::<Sky>().unwrap().expand(1_000_000_000_000_000_000_000_000_000_000);
+        assert_eq!(
+            82 * 999_999_999_999_999_999_999_999_999_999 + 292,
+            huge.sum_distance()
+        );
     }
 }
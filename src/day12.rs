@@ -1,15 +1,70 @@
-use ahash::{HashMap, HashMapExt};
+use ahash::{AHashMap, HashMap, HashMapExt};
 use itertools::{repeat_n, Itertools};
+use rayon::prelude::*;
 use std::cmp::min;
 
 use num::integer::binomial;
 
-fn count_unfolded_matches<'a, 'b>(record: &'a str, damaged: &'b [usize]) -> usize {
-    let record = repeat_n(record, 5).join("?");
-    let damaged: Vec<_> = (0..5).flat_map(|_| damaged.iter()).copied().collect();
-    let mut memo = HashMap::new();
+fn unfold(record: &str, damaged: &[usize], factor: usize) -> (String, Vec<usize>) {
+    (repeat_n(record, factor).join("?"), damaged.repeat(factor))
+}
+
+/// matches counts, by (record, damaged) suffixes
+trait Memo<'a> {
+    fn get(&self, record: &'a [u8], damaged: &'a [usize]) -> Option<usize>;
+    fn insert(&mut self, record: &'a [u8], damaged: &'a [usize], count: usize);
+}
+
+/// memo for a single record, borrowing its suffixes
+impl<'a> Memo<'a> for HashMap<(&'a [u8], &'a [usize]), usize> {
+    fn get(&self, record: &'a [u8], damaged: &'a [usize]) -> Option<usize> {
+        HashMap::get(self, &(record, damaged)).copied()
+    }
+
+    fn insert(&mut self, record: &'a [u8], damaged: &'a [usize], count: usize) {
+        HashMap::insert(self, (record, damaged), count);
+    }
+}
+
+type SlicePosition = (usize, usize);
+
+fn position<T>(slice: &[T]) -> SlicePosition {
+    (slice.as_ptr() as usize, slice.len())
+}
+
+/// memo kept from one record to the next, each thread owning one.
+/// Few entries of a record would serve another one, so rather than growing with every record,
+/// it is cleared for each of them and only keeps its allocation.
+/// Keys are the positions of the suffixes instead of their contents, hashed in constant time,
+/// and only meaningful for the record being counted
+#[derive(Debug, Default)]
+struct ReusableMemo(AHashMap<(SlicePosition, SlicePosition), usize>);
+
+impl ReusableMemo {
+    fn count_matches(&mut self, record: &[u8], damaged: &[usize]) -> usize {
+        self.0.clear();
+        inner_compute_matches(self, record, damaged)
+    }
+}
 
-    inner_compute_matches(&mut memo, record.as_bytes(), &damaged)
+impl<'a> Memo<'a> for ReusableMemo {
+    fn get(&self, record: &'a [u8], damaged: &'a [usize]) -> Option<usize> {
+        self.0.get(&(position(record), position(damaged))).copied()
+    }
+
+    fn insert(&mut self, record: &'a [u8], damaged: &'a [usize], count: usize) {
+        self.0.insert((position(record), position(damaged)), count);
+    }
+}
+
+fn count_unfolded_matches_reusing(
+    memo: &mut ReusableMemo,
+    record: &str,
+    damaged: &[usize],
+    factor: usize,
+) -> usize {
+    let (record, damaged) = unfold(record, damaged, factor);
+    memo.count_matches(record.as_bytes(), &damaged)
 }
 
 fn minimum_len(damaged: &[usize]) -> usize {
@@ -42,10 +97,13 @@ const OK: u8 = b'.';
 const ANY: u8 = b'?';
 const DMG: u8 = b'#';
 
- fn inner_compute_matches<'a, 'b> (memo : &mut HashMap<(&'a[u8],&'b[usize]),usize>, record: &'a[u8], damaged: &'b[usize]) -> usize {
-
-    if let Some(result) = memo.get(&(record, damaged)) {
-        return *result;
+fn inner_compute_matches<'a>(
+    memo: &mut impl Memo<'a>,
+    record: &'a [u8],
+    damaged: &'a [usize],
+) -> usize {
+    if let Some(result) = memo.get(record, damaged) {
+        return result;
     }
     // let's trim record from its OK chars
     let forced_ok = record.iter().take_while(|c| **c == OK).count();
@@ -53,15 +111,14 @@ const DMG: u8 = b'#';
     let forced_ok = record.iter().rev().take_while(|c| **c == OK).count();
     let record = &record[..record.len() - forced_ok];
     if minimum_len(damaged) > record.len() {
-        memo.insert((record,damaged), 0);
+        memo.insert(record, damaged, 0);
         return 0;
     }
 
     if damaged.is_empty() {
-        let result =  if record.contains(&DMG) { 0 } else { 1 };
-        memo.insert((record,damaged), result);
+        let result = if record.contains(&DMG) { 0 } else { 1 };
+        memo.insert(record, damaged, result);
         return result;
-
     }
 
     let leading_choices = record.iter().take_while(|c| **c == ANY).count();
@@ -110,23 +167,122 @@ const DMG: u8 = b'#';
             }).sum()
         }
     };
-    memo.insert((record,damaged), result);
+    memo.insert(record, damaged, result);
     result
 }
 
+/// lazily yields the concrete arrangements of a record (every '?' resolved), in a stable order.
+/// Since matches counts are known for each choice, any arrangement can be reached directly,
+/// without walking through the previous ones
+struct Arrangements<'a> {
+    record: &'a [u8],
+    damaged: &'a [usize],
+    memo: HashMap<(&'a [u8], &'a [usize]), usize>,
+    next: usize,
+    count: usize,
+}
+
+impl<'a> Arrangements<'a> {
+    fn new(record: &'a str, damaged: &'a [usize]) -> Self {
+        let mut memo = HashMap::new();
+        let count = inner_compute_matches(&mut memo, record.as_bytes(), damaged);
+        Self {
+            record: record.as_bytes(),
+            damaged,
+            memo,
+            next: 0,
+            count,
+        }
+    }
+
+    fn count_from(&mut self, pos: usize, group: usize) -> usize {
+        inner_compute_matches(&mut self.memo, &self.record[pos..], &self.damaged[group..])
+    }
+
+    /// at each position, arrangements with an operational spring come before the damaged ones
+    fn arrangement(&mut self, mut index: usize) -> Option<String> {
+        if index >= self.count {
+            return None;
+        }
+        let len = self.record.len();
+        let mut arrangement = Vec::with_capacity(len);
+        let (mut pos, mut group) = (0, 0);
+        while pos < len {
+            if self.record[pos] != DMG {
+                let count = self.count_from(pos + 1, group);
+                if index < count {
+                    arrangement.push(OK);
+                    pos += 1;
+                    continue;
+                }
+                index -= count;
+            }
+            // index is within arrangements starting a damaged group here
+            let size = self.damaged[group];
+            arrangement.extend(repeat_n(DMG, size));
+            if pos + size < len {
+                arrangement.push(OK);
+            }
+            pos = min(pos + size + 1, len);
+            group += 1;
+        }
+        String::from_utf8(arrangement).ok()
+    }
+
+    fn page(&mut self, page: usize, page_size: usize) -> Vec<String> {
+        (page * page_size..(page + 1) * page_size).map_while(|i| self.arrangement(i)).collect()
+    }
+
+    /// evenly spread arrangements
+    fn sample(&mut self, size: usize) -> Vec<String> {
+        let (count, size) = (self.count, min(size, self.count));
+        (0..size).filter_map(|i| self.arrangement(i * count / size)).collect()
+    }
+}
+
+impl Iterator for Arrangements<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let arrangement = self.arrangement(self.next);
+        self.next += 1;
+        arrangement
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.count.saturating_sub(self.next);
+        (remaining, Some(remaining))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.next += n;
+        self.next()
+    }
+}
+
+impl ExactSizeIterator for Arrangements<'_> {}
+
 fn sum_arrangements(
     inputs: &'static str,
     counter: impl Fn(&str, &[usize]) -> usize + Send + Sync,
+) -> usize {
+    sum_arrangements_with(inputs, || (), |_, r, d| counter(r, d))
+}
+
+/// each thread gets its own state from init, kept from one record to the next
+fn sum_arrangements_with<T>(
+    inputs: &'static str,
+    init: impl Fn() -> T + Send + Sync,
+    counter: impl Fn(&mut T, &str, &[usize]) -> usize + Send + Sync,
 ) -> usize {
     inputs
         .lines()
         .filter(|l| !l.is_empty())
-        .enumerate()
-        // .par_bridge()
-        .map(|(_i, l)| {
+        .par_bridge()
+        .map_init(init, |state, l| {
             let (record, damaged) = l.split_once(' ').unwrap();
             let damaged: Vec<usize> = damaged.split(',').filter_map(|g| g.parse().ok()).collect();
-            counter(record, &damaged)
+            counter(state, record, &damaged)
         })
         .sum()
 }
@@ -136,8 +292,27 @@ pub fn arrange_springs() {
     let sum = sum_arrangements(input, compute_matches);
     println!("sum {sum}");
 
-    let sum_unfold = sum_arrangements(input, count_unfolded_matches);
+    // records are evaluated concurrently, each thread reusing its memo
+    let sum_unfold = sum_arrangements_with(input, ReusableMemo::default, |memo, r, d| {
+        count_unfolded_matches_reusing(memo, r, d, 5)
+    });
     println!("sum_unfold {sum_unfold}");
+
+    let (record, damaged) = input.lines().next().unwrap().split_once(' ').unwrap();
+    let damaged: Vec<usize> = damaged.split(',').filter_map(|g| g.parse().ok()).collect();
+    let (record, damaged) = unfold(record, &damaged, 5);
+    let mut arrangements = Arrangements::new(&record, &damaged);
+    println!(
+        "{record} : {} unfolded arrangements, starting with",
+        arrangements.count
+    );
+    for arrangement in arrangements.page(0, 3) {
+        println!("  {arrangement}");
+    }
+    println!("and among which");
+    for arrangement in arrangements.sample(3) {
+        println!("  {arrangement}");
+    }
 }
 
 #[cfg(test)]
@@ -145,8 +320,15 @@ mod tests {
     use super::*;
     use indoc::indoc;
 
+    /// reference counting, with a memo of its own
+    fn count_unfolded_matches(record: &str, damaged: &[usize], factor: usize) -> usize {
+        let (record, damaged) = unfold(record, damaged, factor);
+        compute_matches(&record, &damaged)
+    }
+
     #[test]
     fn aoc_example_works() {
+        let unfolded5 = |r: &str, d: &[usize]| count_unfolded_matches(r, d, 5);
         assert_eq!(
             150,
             sum_arrangements("?????????###???????? 2,1,3,2,1", compute_matches)
@@ -181,27 +363,113 @@ mod tests {
         assert_eq!(1, sum_arrangements("???.### 1,1,3", compute_matches));
         assert_eq!(
             sum_arrangements("#.?#.?#.?#.?#. 1,1,1,1,1", compute_matches),
-            sum_arrangements("#. 1", count_unfolded_matches)
+            sum_arrangements("#. 1", unfolded5)
+        );
+        assert_eq!(16384, sum_arrangements(".??..??...?##. 1,1,3", unfolded5));
+        assert_eq!(1, sum_arrangements("?#?#?#?#?#?#?#? 1,3,1,6", unfolded5));
+        assert_eq!(16, sum_arrangements("????.#...#... 4,1,1", unfolded5));
+        assert_eq!(
+            2500,
+            sum_arrangements("????.######..#####. 1,6,5", unfolded5)
+        );
+        assert_eq!(506250, sum_arrangements("?###???????? 3,2,1", unfolded5));
+    }
+
+    #[test]
+    fn unfold_factor_works() {
+        let input = indoc! {"
+            ???.### 1,1,3
+            .??..??...?##. 1,1,3
+            ?#?#?#?#?#?#?#? 1,3,1,6
+            ????.#...#... 4,1,1
+            ????.######..#####. 1,6,5
+            ?###???????? 3,2,1
+        "};
+        assert_eq!(
+            21,
+            sum_arrangements(input, |r, d| count_unfolded_matches(r, d, 1))
         );
         assert_eq!(
-            16384,
-            sum_arrangements(".??..??...?##. 1,1,3", count_unfolded_matches)
+            525152,
+            sum_arrangements(input, |r, d| count_unfolded_matches(r, d, 5))
         );
+
+        for factor in 1..8 {
+            assert_eq!(
+                sum_arrangements(input, |r, d| count_unfolded_matches(r, d, factor)),
+                sum_arrangements_with(input, ReusableMemo::default, |memo, r, d| {
+                    count_unfolded_matches_reusing(memo, r, d, factor)
+                })
+            );
+        }
+        // the same memo, across records and factors
+        let mut memo = ReusableMemo::default();
+        for factor in 1..8 {
+            for l in input.lines() {
+                let (record, damaged) = l.split_once(' ').unwrap();
+                let damaged: Vec<usize> = damaged.split(',').map(|g| g.parse().unwrap()).collect();
+                assert_eq!(
+                    count_unfolded_matches(record, &damaged, factor),
+                    count_unfolded_matches_reusing(&mut memo, record, &damaged, factor)
+                );
+            }
+        }
         assert_eq!(
-            1,
-            sum_arrangements("?#?#?#?#?#?#?#? 1,3,1,6", count_unfolded_matches)
+            count_unfolded_matches("?###????????", &[3, 2, 1], 10),
+            count_unfolded_matches_reusing(&mut memo, "?###????????", &[3, 2, 1], 10)
         );
+    }
+
+    #[test]
+    fn arrangements_works() {
+        let matches = |arrangement: &str, damaged: &[usize]| {
+            let groups: Vec<usize> =
+                arrangement.split('.').filter(|g| !g.is_empty()).map(str::len).collect();
+            groups == damaged
+        };
+
+        for (record, damaged) in [
+            ("???.###", vec![1, 1, 3]),
+            (".??..??...?##.", vec![1, 1, 3]),
+            ("?###????????", vec![3, 2, 1]),
+            ("????.######..#####.", vec![1, 6, 5]),
+            ("#.#", vec![2]),
+        ] {
+            let arrangements: Vec<String> = Arrangements::new(record, &damaged).collect();
+            assert_eq!(compute_matches(record, &damaged), arrangements.len());
+            assert_eq!(arrangements.len(), arrangements.iter().unique().count());
+            for arrangement in &arrangements {
+                assert!(matches(arrangement, &damaged), "{arrangement} for {record}");
+                assert!(record.chars().zip(arrangement.chars()).all(|(r, a)| r == '?' || r == a));
+            }
+        }
         assert_eq!(
-            16,
-            sum_arrangements("????.#...#... 4,1,1", count_unfolded_matches)
+            vec!["#.#.###"],
+            Arrangements::new("???.###", &[1, 1, 3]).collect_vec()
         );
+
+        // 506250 unfolded arrangements : direct access instead of enumerating them
+        let (record, damaged) = unfold("?###????????", &[3, 2, 1], 5);
+        let mut arrangements = Arrangements::new(&record, &damaged);
+        assert_eq!(506250, arrangements.len());
+        let last = arrangements.nth(506249).unwrap();
+        assert!(matches(&last, &damaged));
+        assert_eq!(None, arrangements.next());
+
+        let mut arrangements = Arrangements::new(&record, &damaged);
+        let page = arrangements.page(2, 10);
         assert_eq!(
-            2500,
-            sum_arrangements("????.######..#####. 1,6,5", count_unfolded_matches)
+            page,
+            Arrangements::new(&record, &damaged).skip(20).take(10).collect_vec()
         );
+        assert_eq!(5, arrangements.sample(5).iter().unique().count());
+        // fewer arrangements than asked for : all of them
+        let mut arrangements = Arrangements::new(".??..??...?##.", &[1, 1, 3]);
+        assert_eq!(4, arrangements.len());
         assert_eq!(
-            506250,
-            sum_arrangements("?###???????? 3,2,1", count_unfolded_matches)
+            Arrangements::new(".??..??...?##.", &[1, 1, 3]).collect_vec(),
+            arrangements.sample(10)
         );
+        assert!(arrangements.page(50625, 10).is_empty());
     }
 }
@@ -1,5 +1,4 @@
 use itertools::Itertools;
-use std::cmp::min;
 
 // enum Tile {
 //     Rock,
@@ -18,37 +17,92 @@ fn read_patterns(input: &str) -> Vec<Pattern> {
     }
     result
 }
-fn is_horizontal_reflexion_after(tiles: &[Vec<char>], index: usize) -> bool {
-    if index + 1 >= tiles.len() {
-        return false;
-    };
-
-    let max_len = min(tiles.len() - index - 1, index + 1);
-    for j in 0..max_len {
-        if tiles[index - j] != tiles[index + 1 + j] {
-            // println!("tiles[{}]:'{}'!=tiles[{}]:'{}'",index-j,tiles[index-j].iter().join(""),index+1+j,tiles[index+1+j].iter().join(""));
-            return false;
+/// reflection lines lie after the given row or column index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Axis {
+    Row(usize),
+    Column(usize),
+    /// 180° rotation around the pattern center
+    Center,
+    /// top-left to bottom-right diagonal, for square patterns only
+    Diagonal,
+    /// top-right to bottom-left diagonal, for square patterns only
+    AntiDiagonal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Symmetry {
+    axis: Axis,
+    /// (x, y) of each tile not matching its mirror, the first of the pair in reading order
+    mismatches: Vec<(usize, usize)>,
+}
+
+impl Symmetry {
+    fn score(&self) -> usize {
+        match self.axis {
+            Axis::Row(index) => (index + 1) * 100,
+            Axis::Column(index) => index + 1,
+            _ => 0,
         }
     }
-    true
 }
 
-fn is_vertical_reflexion_after(tiles: &[Vec<char>], index: usize) -> bool {
-    if index + 1 >= tiles[0].len() {
-        return false;
-    };
-
-    let max_len = min(tiles[0].len() - index - 1, index + 1);
-    // println!("max_len {max_len}");
-    for line in tiles {
-        for j in 0..max_len {
-            if line[index - j] != line[index + 1 + j] {
-                // println!("line {j} {} [{}]:'{}'!=[{}]:'{}'",line.iter().join(""),index-j,line[index-j],index+1+j,line[index+1+j]);
-                return false;
+/// tiles not matching their mirror, None as soon as there are more than max_mismatches
+fn find_mismatches(
+    tiles: &[Vec<char>],
+    max_mismatches: usize,
+    mirror: impl Fn(usize, usize) -> Option<(usize, usize)>,
+) -> Option<Vec<(usize, usize)>> {
+    let mut mismatches = vec![];
+    for (y, line) in tiles.iter().enumerate() {
+        for (x, tile) in line.iter().enumerate() {
+            let Some((mx, my)) = mirror(x, y) else {
+                continue;
+            };
+            // each pair is checked once
+            if (my, mx) <= (y, x) {
+                continue;
+            }
+            if *tile != tiles[my][mx] {
+                mismatches.push((x, y));
+                if mismatches.len() > max_mismatches {
+                    return None;
+                }
             }
         }
     }
-    true
+    Some(mismatches)
+}
+
+/// every symmetry of the pattern, once exactly `smudges` tiles are fixed
+fn find_symmetries(tiles: &[Vec<char>], smudges: usize) -> Vec<Symmetry> {
+    let maxy = tiles.len();
+    let maxx = tiles[0].len();
+    let mut axes: Vec<Axis> = (0..maxy - 1).map(Axis::Row).collect();
+    axes.extend((0..maxx - 1).map(Axis::Column));
+    axes.push(Axis::Center);
+    if maxx == maxy {
+        axes.extend([Axis::Diagonal, Axis::AntiDiagonal]);
+    }
+
+    axes.into_iter()
+        .filter_map(|axis| {
+            let mirror = |x: usize, y: usize| match axis {
+                Axis::Row(index) => {
+                    (2 * index + 1).checked_sub(y).filter(|my| *my < maxy).map(|my| (x, my))
+                }
+                Axis::Column(index) => {
+                    (2 * index + 1).checked_sub(x).filter(|mx| *mx < maxx).map(|mx| (mx, y))
+                }
+                Axis::Center => Some((maxx - 1 - x, maxy - 1 - y)),
+                Axis::Diagonal => Some((y, x)),
+                Axis::AntiDiagonal => Some((maxy - 1 - y, maxx - 1 - x)),
+            };
+            find_mismatches(tiles, smudges, mirror)
+                .filter(|mismatches| mismatches.len() == smudges)
+                .map(|mismatches| Symmetry { axis, mismatches })
+        })
+        .collect()
 }
 
 fn _print_tiles(tiles: &[Vec<char>]) {
@@ -57,76 +111,16 @@ fn _print_tiles(tiles: &[Vec<char>]) {
     }
 }
 
+fn sum_note_with_smudges(tiles: &[Vec<char>], smudges: usize) -> usize {
+    find_symmetries(tiles, smudges).iter().map(Symmetry::score).sum()
+}
+
 fn sum_note_with_correction(tiles: &[Vec<char>]) -> usize {
-    let maxx = tiles[0].len();
-    let maxy = tiles.len();
-    (0..maxx)
-        .flat_map(|i| (0..maxy).map(move |j| (i, j)))
-        .map(|(i, j)| {
-            let mut new_tiles: Vec<Vec<char>> = tiles.to_vec();
-            new_tiles[j][i] = if tiles[j][i] == '.' { '#' } else { '.' };
-
-            (0..maxy - 1)
-                .filter(|vidx| {
-                    if *vidx >= j {
-                        //reflexion below the spot
-                        if *vidx - j + 1 > maxy - *vidx - 1 {
-                            false
-                        } else {
-                            is_horizontal_reflexion_after(&new_tiles, *vidx)
-                        }
-                    } else {
-                        false
-                        // if j- *vidx > *vidx+1 {false}else {is_horizontal_reflexion_after(&new_tiles, *vidx)}
-                        // reflexion uppon the spo
-                    }
-                })
-                .map(|i| {
-                    /*println!("horz refl after {i}");*/
-                    (i + 1) * 100
-                })
-                .sum::<usize>()
-                + (0..maxx - 1)
-                    .filter(|hidx| {
-                        if *hidx >= i {
-                            //reflexion below the spot
-                            if *hidx - i + 1 > maxx - *hidx - 1 {
-                                false
-                            } else {
-                                is_vertical_reflexion_after(&new_tiles, *hidx)
-                            }
-                        } else {
-                            false
-                            // if i- *hidx > *hidx+1 {false}else {is_vertical_reflexion_after(&new_tiles, *hidx)}
-                            // reflexion uppon the spo
-                        }
-                    })
-                    .map(|j| {
-                        /*println!("vert refl after {j}");*/
-                        j + 1
-                    })
-                    .sum::<usize>()
-        })
-        .sum()
+    sum_note_with_smudges(tiles, 1)
 }
 
 fn sum_note(tiles: &[Vec<char>]) -> usize {
-    // println!("----");
-
-    (0..tiles.len() - 1)
-        .filter(|i| is_horizontal_reflexion_after(tiles, *i))
-        .map(|i| {
-            /*println!("horz refl after {i}");*/
-            (i + 1) * 100
-        })
-        .sum::<usize>()
-        + (0..tiles[0].len() - 1)
-            .filter(|j| is_vertical_reflexion_after(tiles, *j))
-            .map(|j| {
-                /*println!("vert refl after {j}");*/
-                j + 1
-            })
-            .sum::<usize>()
+    sum_note_with_smudges(tiles, 0)
 }
 
 pub fn check_notes() {
@@ -165,10 +159,13 @@ mod tests {
             ..##..###
             #....#..#
         "});
-        assert!(is_vertical_reflexion_after(&patterns[0].tiles, 4));
-        assert!(!is_vertical_reflexion_after(&patterns[0].tiles, 7));
+        let axes = |tiles, smudges| {
+            find_symmetries(tiles, smudges).into_iter().map(|s| s.axis).collect::<Vec<_>>()
+        };
+        assert!(axes(&patterns[0].tiles, 0).contains(&Axis::Column(4)));
+        assert!(!axes(&patterns[0].tiles, 0).contains(&Axis::Column(7)));
 
-        assert!(is_horizontal_reflexion_after(&patterns[1].tiles, 3));
+        assert!(axes(&patterns[1].tiles, 0).contains(&Axis::Row(3)));
 
         assert_eq!(
             405,
@@ -183,4 +180,66 @@ mod tests {
                 + sum_note_with_correction(&patterns[1].tiles)
         );
     }
+
+    #[test]
+    fn symmetries_works() {
+        let patterns = read_patterns(indoc! {"
+            #.##..##.
+            ..#.##.#.
+            ##......#
+            ##......#
+            ..#.##.#.
+            ..##..##.
+            #.#.##.#.
+
+            #..
+            .#.
+            ..#
+
+            ##.
+            #..
+            ...
+
+            #.#
+            ...
+            #.#
+        "});
+        assert_eq!(
+            vec![Symmetry {
+                axis: Axis::Row(2),
+                mismatches: vec![(0, 0)]
+            }],
+            find_symmetries(&patterns[0].tiles, 1)
+                .into_iter()
+                .filter(|s| matches!(s.axis, Axis::Row(_)))
+                .collect::<Vec<_>>()
+        );
+
+        let axes = |tiles, smudges| {
+            find_symmetries(tiles, smudges).into_iter().map(|s| s.axis).collect::<Vec<_>>()
+        };
+        // symmetric along both diagonals implies a 180° rotation symmetry
+        assert_eq!(
+            vec![Axis::Center, Axis::Diagonal, Axis::AntiDiagonal],
+            axes(&patterns[1].tiles, 0)
+        );
+        assert_eq!(vec![Axis::Diagonal], axes(&patterns[2].tiles, 0));
+        // 3 smudges away from a 180° rotation : (0,0), (1,0) and (0,1) against (2,2), (1,2) and (2,1)
+        let center = find_symmetries(&patterns[2].tiles, 3)
+            .into_iter()
+            .filter(|s| s.axis == Axis::Center)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![Symmetry {
+                axis: Axis::Center,
+                mismatches: vec![(0, 0), (1, 0), (0, 1)]
+            }],
+            center
+        );
+        assert_eq!(
+            vec![Axis::Center, Axis::Diagonal, Axis::AntiDiagonal],
+            axes(&patterns[3].tiles, 0)
+        );
+        assert_eq!(0, sum_note_with_smudges(&patterns[3].tiles, 0));
+    }
 }
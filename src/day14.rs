@@ -1,20 +1,89 @@
-use crate::day14::Axis::{Horizontal, Vertical};
 use ahash::AHashMap;
 use std::fmt::{Display, Formatter, Write};
+use std::hash::Hash;
 use std::str::FromStr;
 
+/// fixed size bitset, for a row or a column of the dish
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+struct BitLine(Vec<u64>);
+
+impl BitLine {
+    fn new(len: usize) -> Self {
+        Self(vec![0; len.div_ceil(64)])
+    }
+
+    fn get(&self, i: usize) -> bool {
+        self.0[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn set(&mut self, i: usize) {
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+
+    fn count(&self) -> usize {
+        self.0.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// words overlapping [start, end), with the mask of the bits within the range
+    fn masks(start: usize, end: usize) -> impl Iterator<Item = (usize, u64)> {
+        (start / 64..end.div_ceil(64)).map(move |w| {
+            let low = start.max(w * 64) - w * 64;
+            let high = end.min(w * 64 + 64) - w * 64;
+            let mask = if high - low == 64 {
+                u64::MAX
+            } else {
+                ((1 << (high - low)) - 1) << low
+            };
+            (w, mask)
+        })
+    }
+
+    fn count_range(&self, start: usize, end: usize) -> usize {
+        Self::masks(start, end).map(|(w, mask)| (self.0[w] & mask).count_ones() as usize).sum()
+    }
+
+    fn clear_range(&mut self, start: usize, end: usize) {
+        for (w, mask) in Self::masks(start, end) {
+            self.0[w] &= !mask;
+        }
+    }
+
+    fn set_range(&mut self, start: usize, end: usize) {
+        for (w, mask) in Self::masks(start, end) {
+            self.0[w] |= mask;
+        }
+    }
+
+    fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().enumerate().flat_map(|(w, word)| {
+            let mut word = *word;
+            std::iter::from_fn(move || {
+                (word != 0).then(|| {
+                    let bit = word.trailing_zeros() as usize;
+                    word &= word - 1;
+                    w * 64 + bit
+                })
+            })
+        })
+    }
+}
+
+/// rounded rocks are kept both by rows and by columns : tilting only packs them within
+/// the free spans between cubed rocks, in the rows or in the columns, then the other view is rebuilt
 #[derive(Debug, Clone, Eq, PartialEq)]
 struct Scene {
     maxx: usize,
     maxy: usize,
-    rounded: Vec<[usize; 2]>,
-    cubed: Vec<[usize; 2]>,
-}
-
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-enum Axis {
-    Horizontal,
-    Vertical,
+    /// rounded rocks, bit x of row y
+    rows: Vec<BitLine>,
+    /// rounded rocks, bit y of column x
+    cols: Vec<BitLine>,
+    /// cubed rocks, bit x of row y
+    cubed: Vec<BitLine>,
+    /// [start, end) spans without cubed rocks, for each row
+    row_spans: Vec<Vec<(usize, usize)>>,
+    /// [start, end) spans without cubed rocks, for each column
+    col_spans: Vec<Vec<(usize, usize)>>,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -25,13 +94,18 @@ enum Direction {
     East,
 }
 
-impl From<Axis> for usize {
-    fn from(value: Axis) -> Self {
-        match value {
-            Axis::Horizontal => 0,
-            Axis::Vertical => 1,
+fn free_spans(len: usize, is_cubed: impl Fn(usize) -> bool) -> Vec<(usize, usize)> {
+    let mut spans = vec![];
+    let mut start = 0;
+    for i in 0..=len {
+        if i == len || is_cubed(i) {
+            if start < i {
+                spans.push((start, i));
+            }
+            start = i + 1;
         }
     }
+    spans
 }
 
 impl FromStr for Scene {
@@ -41,139 +115,171 @@ impl FromStr for Scene {
         let chars: Vec<Vec<char>> =
             s.lines().filter(|l| !l.is_empty()).map(|l| l.chars().collect()).collect();
 
-        let chars = &chars;
-
         let maxy = chars.len();
         let maxx = chars[0].len();
-        let rounded = (0..maxx)
-            .flat_map(|x| (0..maxy).filter_map(move |y| (chars[y][x] == 'O').then_some([x, y])))
-            .collect();
-        let cubed = (0..maxx)
-            .flat_map(|x| (0..maxy).filter_map(move |y| (chars[y][x] == '#').then_some([x, y])))
-            .collect();
+        let mut rows = vec![BitLine::new(maxx); maxy];
+        let mut cubed = vec![BitLine::new(maxx); maxy];
+        for (y, line) in chars.iter().enumerate() {
+            for (x, c) in line.iter().enumerate() {
+                match c {
+                    'O' => rows[y].set(x),
+                    '#' => cubed[y].set(x),
+                    _ => {}
+                }
+            }
+        }
+        let row_spans = cubed.iter().map(|c| free_spans(maxx, |x| c.get(x))).collect();
+        let col_spans = (0..maxx).map(|x| free_spans(maxy, |y| cubed[y].get(x))).collect();
 
-        Ok(Self {
+        let mut scene = Self {
             maxx,
             maxy,
-            rounded,
+            rows,
+            cols: vec![BitLine::new(maxy); maxx],
             cubed,
-        })
+            row_spans,
+            col_spans,
+        };
+        scene.cols = Self::transpose(&scene.rows, maxx, maxy);
+        Ok(scene)
     }
 }
 impl Scene {
     fn weight(&self) -> usize {
-        self.rounded.iter().map(|[_, y]| self.maxy - y).sum()
+        self.rows.iter().enumerate().map(|(y, row)| row.count() * (self.maxy - y)).sum()
     }
 
-    fn move_to_origin(&self, round: &[usize; 2], axis: Axis) -> [usize; 2] {
-        let m_axis = axis as usize;
-        let f_axis = 1 - m_axis;
-        let start = round[m_axis];
-        let f_col_row = round[f_axis];
-        let next_cubed_pos = self
-            .cubed
-            .iter()
-            .filter(|c| c[f_axis] == f_col_row && c[m_axis] < start)
-            .map(|c| c[m_axis])
-            .max();
-        let number_of_rounded_before = self
-            .rounded
-            .iter()
-            .filter(|r| {
-                r[f_axis] == f_col_row
-                    && r[m_axis] < start
-                    && if let Some(next_cubed_pos) = next_cubed_pos {
-                        r[m_axis] > next_cubed_pos
-                    } else {
-                        true
-                    }
-            })
-            .count();
+    fn transpose(lines: &[BitLine], len: usize, transposed_len: usize) -> Vec<BitLine> {
+        let mut transposed = vec![BitLine::new(transposed_len); len];
+        for (i, line) in lines.iter().enumerate() {
+            for j in line.ones() {
+                transposed[j].set(i);
+            }
+        }
+        transposed
+    }
 
-        let mut result = *round;
-        result[m_axis] = if let Some(next_cubed_pos) = next_cubed_pos {
-            next_cubed_pos + 1
-        } else {
-            0
-        } + number_of_rounded_before;
-        result
-    }
-    fn move_away_origin(&self, round: &[usize; 2], axis: Axis) -> [usize; 2] {
-        let m_axis = axis as usize;
-        let f_axis = 1 - m_axis;
-        let start = round[m_axis];
-        let f_col_row = round[f_axis];
-        let next_cubed_pos = self
-            .cubed
-            .iter()
-            .filter(|c| c[f_axis] == f_col_row && c[m_axis] > start)
-            .map(|c| c[m_axis])
-            .min();
-        let number_of_rounded_after = self
-            .rounded
-            .iter()
-            .filter(|r| {
-                r[f_axis] == f_col_row
-                    && r[m_axis] > start
-                    && if let Some(next_cubed_pos) = next_cubed_pos {
-                        r[m_axis] < next_cubed_pos
-                    } else {
-                        true
-                    }
-            })
-            .count();
+    /// packs rounded rocks at the start (or the end) of each free span
+    fn pack(lines: &mut [BitLine], spans: &[Vec<(usize, usize)>], to_start: bool) {
+        for (line, spans) in lines.iter_mut().zip(spans) {
+            for (start, end) in spans {
+                let count = line.count_range(*start, *end);
+                if count == 0 || count == end - start {
+                    continue;
+                }
+                line.clear_range(*start, *end);
+                if to_start {
+                    line.set_range(*start, start + count);
+                } else {
+                    line.set_range(end - count, *end);
+                }
+            }
+        }
+    }
+
+    fn tilt(&mut self, direction: Direction) {
+        match direction {
+            Direction::North | Direction::South => {
+                Self::pack(
+                    &mut self.cols,
+                    &self.col_spans,
+                    direction == Direction::North,
+                );
+                self.rows = Self::transpose(&self.cols, self.maxy, self.maxx);
+            }
+            Direction::West | Direction::East => {
+                Self::pack(
+                    &mut self.rows,
+                    &self.row_spans,
+                    direction == Direction::West,
+                );
+                self.cols = Self::transpose(&self.rows, self.maxx, self.maxy);
+            }
+        }
+    }
+
+    fn set_rows(&mut self, rows: Vec<BitLine>) {
+        self.cols = Self::transpose(&rows, self.maxx, self.maxy);
+        self.rows = rows;
+    }
 
-        let mut result = *round;
-        result[m_axis] = next_cubed_pos.unwrap_or(match axis {
-            Axis::Horizontal => self.maxx,
-            Axis::Vertical => self.maxy,
-        }) - 1
-            - number_of_rounded_after;
+    fn spin(&mut self, sequence: &[Direction]) {
+        for dir in sequence {
+            self.tilt(*dir);
+        }
+    }
 
-        result
+    /// rounded rocks after each spin of the sequence, until they are back to an already seen state
+    fn spin_cycle(&self, sequence: &[Direction]) -> Cycle<Vec<BitLine>> {
+        let mut scene = self.clone();
+        Cycle::detect(self.rows.clone(), |rows| {
+            scene.set_rows(rows.clone());
+            scene.spin(sequence);
+            scene.rows.clone()
+        })
     }
 
-    fn tilt(&self, round: &[[usize; 2]], direction: Direction) -> Vec<[usize; 2]> {
-        let mut result: Vec<[usize; 2]> = round
+    /// weight after each of the given numbers of spins
+    fn spin_weights(&self, sequence: &[Direction], spins: &[usize]) -> Vec<usize> {
+        let cycle = self.spin_cycle(sequence);
+        let mut scene = self.clone();
+        spins
             .iter()
-            .map(|r| match direction {
-                Direction::North => self.move_to_origin(r, Vertical),
-                Direction::West => self.move_to_origin(r, Horizontal),
-                Direction::South => self.move_away_origin(r, Vertical),
-                Direction::East => self.move_away_origin(r, Horizontal),
+            .map(|n| {
+                scene.set_rows(cycle.state_after(*n).clone());
+                scene.weight()
             })
-            .collect();
-        result.sort();
-        result
+            .collect()
     }
-    fn tune(&mut self) {
-        let mut previous: AHashMap<Vec<[usize; 2]>, usize> = AHashMap::new();
 
-        previous.insert(self.rounded.to_vec(), 0);
+    fn tune(&mut self) {
+        let cycle = self.spin_cycle(&SPIN_CYCLE);
+        self.set_rows(cycle.state_after(1_000_000_000).clone());
+    }
+}
 
-        let mut i = 0usize;
+const SPIN_CYCLE: [Direction; 4] = [
+    Direction::North,
+    Direction::West,
+    Direction::South,
+    Direction::East,
+];
 
-        while i < 1000000000 {
-            for dir in [
-                Direction::North,
-                Direction::West,
-                Direction::South,
-                Direction::East,
-            ] {
-                self.rounded = self.tilt(&self.rounded, dir);
-                // self.rounded.sort();
+/// states reached by iterating a deterministic step, up to the first repeated one
+#[derive(Debug, Clone)]
+struct Cycle<S> {
+    /// states[i] is the state after i steps, the first one being the initial state
+    states: Vec<S>,
+    /// steps before entering the cycle
+    start: usize,
+    period: usize,
+}
 
-                // println!("{:?}",self.rounded);
-                // println!("after {dir:?}");
-                // self.print();
-                // println!("\n");
+impl<S: Clone + Hash + Eq> Cycle<S> {
+    fn detect(initial: S, mut step: impl FnMut(&S) -> S) -> Self {
+        let mut seen: AHashMap<S, usize> = AHashMap::new();
+        let mut states = vec![];
+        let mut current = initial;
+        loop {
+            if let Some(start) = seen.get(&current) {
+                return Self {
+                    start: *start,
+                    period: states.len() - start,
+                    states,
+                };
             }
-            i += 1;
-            if previous.contains_key(&self.rounded) {
-                let period = i - previous.get(&self.rounded).unwrap();
-                i += ((1000000000 - i) / period) * period;
-            }
-            previous.insert(self.rounded.clone(), i);
+            seen.insert(current.clone(), states.len());
+            let next = step(&current);
+            states.push(current);
+            current = next;
+        }
+    }
+
+    fn state_after(&self, steps: usize) -> &S {
+        if steps < self.start {
+            &self.states[steps]
+        } else {
+            &self.states[self.start + (steps - self.start) % self.period]
         }
     }
 }
@@ -181,9 +287,9 @@ impl Display for Scene {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         for y in 0..self.maxy {
             for x in 0..self.maxx {
-                f.write_char(if self.cubed.contains(&[x, y]) {
+                f.write_char(if self.cubed[y].get(x) {
                     '#'
-                } else if self.rounded.contains(&[x, y]) {
+                } else if self.rows[y].get(x) {
                     'O'
                 } else {
                     '.'
@@ -199,7 +305,7 @@ pub fn tune_parabol() {
     let scene: Scene = input.parse().unwrap();
 
     let mut scene1 = scene.clone();
-    scene1.rounded = scene1.tilt(&scene1.rounded, Direction::North);
+    scene1.tilt(Direction::North);
     let weight = scene1.weight();
     println!("weight, {weight}");
 
@@ -207,11 +313,22 @@ pub fn tune_parabol() {
     scene2.tune();
     let weight_tuned = scene2.weight();
     println!("weight_tuned, {weight_tuned}");
+
+    let cycle = scene.spin_cycle(&SPIN_CYCLE);
+    println!(
+        "spin cycle : start {}, period {}, {} states",
+        cycle.start,
+        cycle.period,
+        cycle.states.len()
+    );
+    let weights = scene.spin_weights(&SPIN_CYCLE, &[1, 2, 3, 1000]);
+    println!("weights after 1, 2, 3, 1000 spins {weights:?}");
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::random_grid;
     use indoc::indoc;
     #[test]
     fn tst_aoc_example() {
@@ -228,13 +345,12 @@ mod tests {
             #OO..#....
         "};
         let mut scene: Scene = input.parse().unwrap();
-        scene.rounded = scene.tilt(&scene.rounded, Direction::North);
+        scene.tilt(Direction::North);
         assert_eq!(136, scene.weight());
 
         print!("{scene}");
         let mut scenenorth_west = scene.clone();
-        scenenorth_west.rounded = scenenorth_west.tilt(&scenenorth_west.rounded, Direction::West);
-        scenenorth_west.rounded.sort();
+        scenenorth_west.tilt(Direction::West);
 
         println!("\n***scenenorth_west\n{scenenorth_west}\n-----");
 
@@ -242,4 +358,114 @@ mod tests {
         scene6.tune();
         assert_eq!(64, scene6.weight());
     }
+
+    #[test]
+    fn spin_cycle_works() {
+        let input = indoc! {"
+            O....#....
+            O.OO#....#
+            .....##...
+            OO.#O....O
+            .O.....O#.
+            O.#..O.#.#
+            ..O..#O..O
+            .......O..
+            #....###..
+            #OO..#....
+        "};
+        let scene: Scene = input.parse().unwrap();
+        let cycle = scene.spin_cycle(&SPIN_CYCLE);
+        assert_eq!((3, 7), (cycle.start, cycle.period));
+        assert_eq!(10, cycle.states.len());
+
+        let mut spun = scene.clone();
+        let spins = [0, 1, 2, 3, 10, 25, 1_000_000_000];
+        let brute_force: Vec<usize> = (0..=25)
+            .map(|i| {
+                if i > 0 {
+                    spun.spin(&SPIN_CYCLE);
+                }
+                spun.weight()
+            })
+            .collect();
+        let weights = scene.spin_weights(&SPIN_CYCLE, &spins);
+        assert_eq!(
+            spins[..6].iter().map(|n| brute_force[*n]).collect::<Vec<_>>(),
+            weights[..6]
+        );
+        assert_eq!(64, weights[6]);
+
+        // any sequence : north then south only, the rocks settle after the first spin
+        let cycle = scene.spin_cycle(&[Direction::North, Direction::South]);
+        assert_eq!((1, 1), (cycle.start, cycle.period));
+
+        // any state : the Collatz sequence from 6 enters the 4, 2, 1 loop after 6 steps
+        let collatz = Cycle::detect(6u64, |n| if n % 2 == 0 { n / 2 } else { 3 * n + 1 });
+        assert_eq!((6, 3), (collatz.start, collatz.period));
+        assert_eq!(2, *collatz.state_after(1_000_000));
+    }
+
+    /// rocks rolling one tile at a time, on a char grid
+    fn naive_tilt(grid: &mut [Vec<char>], direction: Direction) {
+        let (dx, dy): (isize, isize) = match direction {
+            Direction::North => (0, -1),
+            Direction::West => (-1, 0),
+            Direction::South => (0, 1),
+            Direction::East => (1, 0),
+        };
+        let (maxx, maxy) = (grid[0].len() as isize, grid.len() as isize);
+        let mut moved = true;
+        while moved {
+            moved = false;
+            for y in 0..maxy {
+                for x in 0..maxx {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if (0..maxx).contains(&nx)
+                        && (0..maxy).contains(&ny)
+                        && grid[y as usize][x as usize] == 'O'
+                        && grid[ny as usize][nx as usize] == '.'
+                    {
+                        grid[y as usize][x as usize] = '.';
+                        grid[ny as usize][nx as usize] = 'O';
+                        moved = true;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn large_dish_works() {
+        // 150 columns : rows span several words
+        let mut grid = random_grid(150, 90, 42, |r| match r >> 60 {
+            0..=1 => '#',
+            2..=5 => 'O',
+            _ => '.',
+        });
+        let mut scene: Scene = grid
+            .iter()
+            .map(|l| l.iter().collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+            .parse()
+            .unwrap();
+        for _ in 0..3 {
+            for dir in [
+                Direction::North,
+                Direction::West,
+                Direction::South,
+                Direction::East,
+            ] {
+                scene.tilt(dir);
+                naive_tilt(&mut grid, dir);
+                let expected: String =
+                    grid.iter().map(|l| l.iter().collect::<String>() + "\n").collect();
+                assert_eq!(expected, scene.to_string(), "after {dir:?}");
+            }
+        }
+        assert_eq!(
+            scene.cols,
+            Scene::transpose(&scene.rows, scene.maxx, scene.maxy)
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use eyre::{eyre, Error};
 use std::fmt::{Display, Formatter, Write};
 
 fn factory_hash(input: &str) -> usize {
@@ -9,14 +10,45 @@ fn sum_instructions_hash(input: &str) -> usize {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
-struct Lens {
-    label: &'static str,
+struct Lens<'a> {
+    label: &'a str,
     focal: usize,
 }
 
-// TODO : use proper boxes
-struct Boxes<'b>(&'b [Vec<Lens>]);
-impl<'b> Display for Boxes<'b> {
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Instruction<'a> {
+    /// `label-`
+    Remove(&'a str),
+    /// `label=focal`
+    Insert(Lens<'a>),
+}
+
+impl<'a> Instruction<'a> {
+    fn parse(step: &'a str) -> Result<Self, Error> {
+        if let Some(label) = step.strip_suffix('-') {
+            if label.is_empty() {
+                return Err(eyre!("no label in '{step}'"));
+            }
+            Ok(Self::Remove(label))
+        } else if let Some((label, focal)) = step.split_once('=') {
+            if label.is_empty() {
+                return Err(eyre!("no label in '{step}'"));
+            }
+            let focal =
+                focal.parse().map_err(|e| eyre!("wrong focal '{focal}' in '{step}' : {e}"))?;
+            Ok(Self::Insert(Lens { label, focal }))
+        } else {
+            Err(eyre!("no operation in '{step}'"))
+        }
+    }
+}
+
+fn parse_instructions(input: &str) -> Result<Vec<Instruction<'_>>, Error> {
+    input.trim_end().split(',').map(Instruction::parse).collect()
+}
+
+struct Boxes<'b, 'a>(&'b [Vec<Lens<'a>>]);
+impl Display for Boxes<'_, '_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         for (i, b) in self.0.iter().enumerate() {
             if !b.is_empty() {
@@ -31,57 +63,145 @@ impl<'b> Display for Boxes<'b> {
     }
 }
 
-fn sum_focusing_power(instructions: &'static str) -> usize {
-    let mut boxes: Vec<Vec<Lens>> = vec![Default::default(); 256];
-    // let mut encoutered_lens: Vec<Lens> = vec![];
-    for str_instruction in instructions.split(',') {
-        // FIXME : define a proper Instruction struct
-        let instruction = str_instruction.as_bytes();
-        if instruction[instruction.len() - 1] as char == '-' {
-            let label = &str_instruction[..str_instruction.len() - 1];
-            let index = factory_hash(label);
-            boxes[index].retain(|l| l.label != label);
-        } else {
-            let label = &str_instruction[..str_instruction.len() - 2];
-            let index = factory_hash(label);
-            let focal: usize = str_instruction[instruction.len() - 1..].parse().unwrap();
-            // encoutered_lens.push(Lens { focal, label });
-            if boxes[index].iter().any(|l| l.label == label) {
-                boxes[index] = boxes[index]
-                    .iter()
-                    .map(|l| {
-                        if l.label == label {
-                            Lens { label, focal }
-                        } else {
-                            *l
-                        }
-                    })
-                    .collect();
-            } else {
-                boxes[index].push(Lens { label, focal })
+/// HASHMAP : lenses are kept in the box given by the hash of their label, in insertion order
+#[derive(Debug, Clone)]
+struct LensBoxes<'a> {
+    boxes: Vec<Vec<Lens<'a>>>,
+    hash: fn(&str) -> usize,
+}
+
+impl<'a> LensBoxes<'a> {
+    fn new() -> Self {
+        Self::with_hash(256, factory_hash)
+    }
+
+    /// `hash` is taken modulo `count`, which must be at least 1
+    fn with_hash(count: usize, hash: fn(&str) -> usize) -> Self {
+        assert!(count > 0, "at least one box is needed");
+        Self {
+            boxes: vec![vec![]; count],
+            hash,
+        }
+    }
+
+    fn box_index(&self, label: &str) -> usize {
+        (self.hash)(label) % self.boxes.len()
+    }
+
+    /// replaces the lens with the same label in place, if any
+    fn insert(&mut self, lens: Lens<'a>) -> Option<Lens<'a>> {
+        let index = self.box_index(lens.label);
+        let b = &mut self.boxes[index];
+        match b.iter_mut().find(|l| l.label == lens.label) {
+            Some(l) => Some(std::mem::replace(l, lens)),
+            None => {
+                b.push(lens);
+                None
             }
         }
-        // println!("\nafter '{str_instruction}':");
-        // println!("{}",Boxes(&boxes));
-    }
-
-    boxes
-        .into_iter()
-        .map(|b| {
-            b.into_iter()
-                .enumerate()
-                .map(|(i, l)| (1 + factory_hash(l.label)) * (i + 1) * l.focal)
-                .sum::<usize>()
-        })
-        .sum()
+    }
+
+    fn remove(&mut self, label: &str) -> Option<Lens<'a>> {
+        let index = self.box_index(label);
+        let b = &mut self.boxes[index];
+        let slot = b.iter().position(|l| l.label == label)?;
+        Some(b.remove(slot))
+    }
+
+    fn get(&self, label: &str) -> Option<&Lens<'a>> {
+        self.boxes[self.box_index(label)].iter().find(|l| l.label == label)
+    }
+
+    fn apply(&mut self, instruction: &Instruction<'a>) {
+        match instruction {
+            Instruction::Remove(label) => {
+                self.remove(label);
+            }
+            Instruction::Insert(lens) => {
+                self.insert(*lens);
+            }
+        }
+    }
+
+    /// (box, slot, lens) for each lens, box by box
+    fn iter(&self) -> impl Iterator<Item = (usize, usize, &Lens<'a>)> {
+        self.boxes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, b)| b.iter().enumerate().map(move |(slot, l)| (i, slot, l)))
+    }
+
+    fn focusing_power(&self) -> usize {
+        self.iter().map(|(i, slot, l)| (i + 1) * (slot + 1) * l.focal).sum()
+    }
+
+    fn display(&self) -> Boxes<'_, 'a> {
+        Boxes(&self.boxes)
+    }
+}
+
+/// every instruction applied so far, to replay the boxes as they were after any step
+struct OperationLog<'a> {
+    initial: LensBoxes<'a>,
+    instructions: Vec<Instruction<'a>>,
+}
+
+impl<'a> OperationLog<'a> {
+    fn new(initial: LensBoxes<'a>) -> Self {
+        Self {
+            initial,
+            instructions: vec![],
+        }
+    }
+
+    fn push(&mut self, instruction: Instruction<'a>) {
+        self.instructions.push(instruction);
+    }
+
+    /// boxes after the first `steps` instructions
+    fn replay(&self, steps: usize) -> LensBoxes<'a> {
+        let mut boxes = self.initial.clone();
+        for instruction in &self.instructions[..steps.min(self.instructions.len())] {
+            boxes.apply(instruction);
+        }
+        boxes
+    }
+}
+
+fn sum_focusing_power(instructions: &str) -> Result<usize, Error> {
+    let mut boxes = LensBoxes::new();
+    for instruction in parse_instructions(instructions)? {
+        boxes.apply(&instruction);
+    }
+    Ok(boxes.focusing_power())
 }
 pub fn init_factory() {
     let instructions = include_str!("../resources/day15_init_instructions.txt");
     let sum = sum_instructions_hash(instructions);
 
     println!("init sum {sum}");
-    let total_power = sum_focusing_power(instructions);
+    let total_power = sum_focusing_power(instructions).unwrap();
     println!("total power {total_power}");
+
+    let mut log = OperationLog::new(LensBoxes::new());
+    for instruction in parse_instructions(instructions).unwrap() {
+        log.push(instruction);
+    }
+    print!("after 3 steps :\n{}", log.replay(3).display());
+    let halfway = log.replay(log.instructions.len() / 2);
+    println!(
+        "after {} steps, {} lenses, power {}",
+        log.instructions.len() / 2,
+        halfway.iter().count(),
+        halfway.focusing_power()
+    );
+    let last_inserted = log.instructions.iter().rev().find_map(|i| match i {
+        Instruction::Insert(lens) => Some(lens.label),
+        Instruction::Remove(_) => None,
+    });
+    if let Some(label) = last_inserted {
+        println!("last inserted lens {:?}", log.replay(usize::MAX).get(label));
+    }
 }
 
 #[cfg(test)]
@@ -102,7 +222,105 @@ mod tests {
 
         assert_eq!(
             145,
-            sum_focusing_power("rn=1,cm-,qp=3,cm=2,qp-,pc=4,ot=9,ab=5,pc-,pc=6,ot=7")
+            sum_focusing_power("rn=1,cm-,qp=3,cm=2,qp-,pc=4,ot=9,ab=5,pc-,pc=6,ot=7").unwrap()
+        );
+    }
+
+    #[test]
+    fn lens_boxes_works() {
+        let instructions =
+            parse_instructions("rn=1,cm-,qp=3,cm=2,qp-,pc=4,ot=9,ab=5,pc-,pc=6,ot=7\n").unwrap();
+        assert_eq!(11, instructions.len());
+        assert_eq!(Instruction::Remove("cm"), instructions[1]);
+        assert_eq!(
+            Instruction::Insert(Lens {
+                label: "ot",
+                focal: 7
+            }),
+            instructions[10]
+        );
+        assert!(Instruction::parse("rn").is_err());
+        assert!(Instruction::parse("-").is_err());
+        assert!(Instruction::parse("=3").is_err());
+        assert!(Instruction::parse("rn=x").is_err());
+        assert!(parse_instructions("rn=1,,cm-").is_err());
+
+        let mut log = OperationLog::new(LensBoxes::new());
+        for instruction in instructions {
+            log.push(instruction);
+        }
+        assert_eq!(
+            "Box(0): [rn 1] [cm 2]\nBox(3): [pc 4]\n",
+            log.replay(6).display().to_string()
+        );
+        assert_eq!(
+            "Box(0): [rn 1] [cm 2]\nBox(3): [pc 4] [ot 9] [ab 5]\n",
+            log.replay(8).display().to_string()
+        );
+        let boxes = log.replay(11);
+        assert_eq!(
+            "Box(0): [rn 1] [cm 2]\nBox(3): [ot 7] [ab 5] [pc 6]\n",
+            boxes.display().to_string()
+        );
+        assert_eq!(145, boxes.focusing_power());
+        assert_eq!(
+            Some(&Lens {
+                label: "pc",
+                focal: 6
+            }),
+            boxes.get("pc")
+        );
+        assert_eq!(None, boxes.get("qp"));
+
+        // 2 boxes by label length parity, the hash being reduced to the boxes count
+        let mut boxes = LensBoxes::with_hash(2, |label| label.len());
+        assert_eq!(
+            None,
+            boxes.insert(Lens {
+                label: "a",
+                focal: 2
+            })
+        );
+        assert_eq!(
+            None,
+            boxes.insert(Lens {
+                label: "bb",
+                focal: 3
+            })
+        );
+        assert_eq!(
+            Some(Lens {
+                label: "a",
+                focal: 2
+            }),
+            boxes.insert(Lens {
+                label: "a",
+                focal: 4
+            })
+        );
+        assert_eq!(
+            vec![(0, 0, "bb"), (1, 0, "a")],
+            boxes.iter().map(|(b, s, l)| (b, s, l.label)).collect::<Vec<_>>()
+        );
+        // 1 * 1 * 3 + 2 * 1 * 4
+        assert_eq!(11, boxes.focusing_power());
+        assert_eq!(
+            Some(Lens {
+                label: "bb",
+                focal: 3
+            }),
+            boxes.remove("bb")
+        );
+        assert_eq!(None, boxes.remove("bb"));
+        // a hash of 3 falls into box 1
+        boxes.insert(Lens {
+            label: "ccc",
+            focal: 5,
+        });
+        assert_eq!(
+            vec![(1, 0, "a"), (1, 1, "ccc")],
+            boxes.iter().map(|(b, s, l)| (b, s, l.label)).collect::<Vec<_>>()
         );
+        assert_eq!(Some(5), boxes.get("ccc").map(|l| l.focal));
     }
 }
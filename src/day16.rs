@@ -1,13 +1,19 @@
 use crate::day16::Direction::*;
+use eyre::{eyre, Error};
 use itertools::Itertools;
+use plotters::prelude::{BitMapBackend, Color, IntoDrawingArea, RGBColor, Rectangle, BLACK};
 use rayon::prelude::*;
-use std::cmp::max;
+use std::cmp::Reverse;
 use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
 use std::str::FromStr;
 
 #[derive(Debug)]
 struct Contraption {
     mirrors: HashMap<(usize, usize), char>,
+    components: Components,
+    /// index of each rotating mirror, in the orientations of a propagation
+    rotating: HashMap<(usize, usize), usize>,
     maxx: usize,
     maxy: usize,
 }
@@ -19,503 +25,533 @@ enum Direction {
     South,
     East,
 }
-impl FromStr for Contraption {
-    type Err = ();
 
+impl Direction {
+    const ALL: [Direction; 4] = [North, West, South, East];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn from_char(c: char) -> Result<Self, Error> {
+        match c {
+            'N' => Ok(North),
+            'W' => Ok(West),
+            'S' => Ok(South),
+            'E' => Ok(East),
+            c => Err(eyre!("unknown direction '{c}'")),
+        }
+    }
+}
+
+/// outgoing directions of a beam entering the component, for each incoming direction
+type Rules = [Vec<Direction>; 4];
+
+/// rules of each orientation of the component. A rotating component turns to its next orientation
+/// each time a beam hits it, the others have a single one
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Component(Vec<Rules>);
+
+impl Component {
+    fn is_rotating(&self) -> bool {
+        self.0.len() > 1
+    }
+}
+
+impl FromStr for Component {
+    type Err = Error;
+
+    /// `N:E E:N ...` for each orientation, separated by `;`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(
+            s.split(';').map(parse_rules).collect::<Result<_, _>>()?,
+        ))
+    }
+}
+
+/// incoming directions not listed are absorbed
+fn parse_rules(s: &str) -> Result<Rules, Error> {
+    let mut rules = Rules::default();
+    for rule in s.split_whitespace() {
+        let (incoming, outgoing) =
+            rule.split_once(':').ok_or_else(|| eyre!("no ':' in rule '{rule}'"))?;
+        let mut incoming = incoming.chars();
+        let dir = match (incoming.next(), incoming.next()) {
+            (Some(c), None) => Direction::from_char(c)?,
+            _ => return Err(eyre!("one incoming direction expected in rule '{rule}'")),
+        };
+        rules[dir.index()] =
+            outgoing.chars().map(Direction::from_char).collect::<Result<_, _>>()?;
+    }
+    Ok(rules)
+}
+
+/// components by tile character, empty space being one of them
+#[derive(Debug, Clone)]
+struct Components(HashMap<char, Component>);
+
+impl FromStr for Components {
+    type Err = Error;
+
+    /// one component by line : its character then its rules, `//` starting a comment line
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mirrors = s
+        let mut components = HashMap::new();
+        for l in s.lines().filter(|l| !l.trim().is_empty() && !l.starts_with("//")) {
+            let mut chars = l.chars();
+            let c = chars.next().unwrap();
+            let component = chars.as_str().parse().map_err(|e| eyre!("{e} for '{c}'"))?;
+            if components.insert(c, component).is_some() {
+                return Err(eyre!("component '{c}' defined twice"));
+            }
+        }
+        if !components.contains_key(&'.') {
+            return Err(eyre!("no empty space component '.'"));
+        }
+        Ok(Self(components))
+    }
+}
+
+impl Default for Components {
+    fn default() -> Self {
+        include_str!("../resources/day16_components.txt").parse().unwrap()
+    }
+}
+
+impl Contraption {
+    fn parse(s: &str, components: Components) -> Result<Self, Error> {
+        let mirrors: HashMap<(usize, usize), char> = s
             .lines()
             .enumerate()
             .flat_map(|(j, l)| l.trim().chars().enumerate().map(move |(i, c)| ((i, j), c)))
             .filter(|(_, c)| *c != '.')
             .collect();
+        if let Some(((x, y), c)) = mirrors.iter().find(|(_, c)| !components.0.contains_key(c)) {
+            return Err(eyre!("unknown component '{c}' at ({x},{y})"));
+        }
+        let rotating = mirrors
+            .iter()
+            .filter(|(_, c)| components.0[c].is_rotating())
+            .map(|(pos, _)| *pos)
+            .sorted()
+            .enumerate()
+            .map(|(i, pos)| (pos, i))
+            .collect();
         let maxy = s.lines().count();
-        let maxx = s.lines().next().unwrap().len();
+        let maxx = s.lines().next().ok_or_else(|| eyre!("empty contraption"))?.len();
         Ok(Self {
             mirrors,
+            components,
+            rotating,
             maxx,
             maxy,
         })
     }
 }
+
+impl FromStr for Contraption {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, Components::default())
+    }
+}
+
+/// a beam entering the (x, y) tile toward dir
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 struct Beam {
     x: usize,
     y: usize,
-    dir: Option<Direction>,
+    dir: Direction,
 }
 
 impl Contraption {
-    fn propagate_step(&self, beam: Beam, inplace: bool) -> Vec<Beam> {
+    fn component(&self, x: usize, y: usize) -> &Component {
+        &self.components.0[self.mirrors.get(&(x, y)).unwrap_or(&'.')]
+    }
+
+    /// beams leaving the tile of the given beam, toward their next tile,
+    /// its component being in the given orientation
+    fn propagate_step(&self, beam: Beam, orientation: usize) -> impl Iterator<Item = Beam> + '_ {
         let Beam { x, y, dir } = beam;
-        if dir.is_none() {
-            return vec![];
+        self.component(x, y).0[orientation][dir.index()].iter().filter_map(move |dir| {
+            let (x, y) = match dir {
+                North => (x, y.checked_sub(1)?),
+                West => (x.checked_sub(1)?, y),
+                South => (x, y + 1),
+                East => (x + 1, y),
+            };
+            (x < self.maxx && y < self.maxy).then_some(Beam { x, y, dir: *dir })
+        })
+    }
+
+    fn propagate(&self) -> HashSet<Beam> {
+        self.propagate_from_edge(Beam {
+            x: 0,
+            y: 0,
+            dir: East,
+        })
+    }
+    /// beams are followed one at a time, the last one found first, each hit turning a rotating mirror.
+    /// A beam is only dropped if it was already followed with every mirror in the same orientation
+    fn propagate_from_edge(&self, beam: Beam) -> HashSet<Beam> {
+        let mut followed: HashSet<(Beam, Vec<usize>)> = Default::default();
+        let mut orientations = vec![0; self.rotating.len()];
+        let mut current_beams: Vec<Beam> = vec![beam];
+
+        while let Some(b) = current_beams.pop() {
+            if !followed.insert((b, orientations.clone())) {
+                continue;
+            }
+            let orientation = match self.rotating.get(&(b.x, b.y)) {
+                Some(i) => {
+                    let orientation = orientations[*i];
+                    orientations[*i] = (orientation + 1) % self.component(b.x, b.y).0.len();
+                    orientation
+                }
+                None => 0,
+            };
+            current_beams.extend(self.propagate_step(b, orientation));
         }
-        if !inplace {
-            if let Some(c) = self.mirrors.get(&(x, y)) {
-                let beams = match c {
-                    '-' => match dir.unwrap() {
-                        North | South => {
-                            vec![
-                                Beam {
-                                    x,
-                                    y,
-                                    dir: Some(West),
-                                },
-                                Beam {
-                                    x,
-                                    y,
-                                    dir: Some(East),
-                                },
-                            ]
-                        }
-                        West | East => {
-                            vec![]
-                        }
-                    },
-                    '/' => match dir.unwrap() {
-                        North => vec![Beam {
-                            x,
-                            y,
-                            dir: Some(East),
-                        }],
-                        West => vec![Beam {
-                            x,
-                            y,
-                            dir: Some(South),
-                        }],
-                        South => vec![Beam {
-                            x,
-                            y,
-                            dir: Some(West),
-                        }],
-                        East => vec![Beam {
-                            x,
-                            y,
-                            dir: Some(North),
-                        }],
-                    },
-                    '\\' => match dir.unwrap() {
-                        North => vec![Beam {
-                            x,
-                            y,
-                            dir: Some(West),
-                        }],
-                        West => vec![Beam {
-                            x,
-                            y,
-                            dir: Some(North),
-                        }],
-                        South => vec![Beam {
-                            x,
-                            y,
-                            dir: Some(East),
-                        }],
-                        East => vec![Beam {
-                            x,
-                            y,
-                            dir: Some(South),
-                        }],
-                    },
-                    '|' => match dir.unwrap() {
-                        North | South => {
-                            vec![]
-                        }
-                        West | East => {
-                            vec![
-                                Beam {
-                                    x,
-                                    y,
-                                    dir: Some(North),
-                                },
-                                Beam {
-                                    x,
-                                    y,
-                                    dir: Some(South),
-                                },
-                            ]
-                        }
-                    },
-                    _ => panic!(),
-                };
-                if !beams.is_empty() {
-                    return beams
-                        .into_iter()
-                        .flat_map(|b| self.propagate_step(b, true).into_iter())
-                        .collect();
+
+        followed.into_iter().map(|(b, _)| b).collect()
+    }
+    /// beams passing through each tile of the contraption, over every given entry
+    fn heatmap(&self, entries: &[Beam]) -> Heatmap {
+        let mut heatmap = Heatmap {
+            maxx: self.maxx,
+            counts: vec![[0; 4]; self.maxx * self.maxy],
+        };
+        for beams in entries.par_iter().map(|b| self.propagate_from_edge(*b)).collect::<Vec<_>>() {
+            heatmap.add(&beams);
+        }
+        heatmap
+    }
+
+    /// components are kept, empty tiles crossed in a single direction show it as an arrow,
+    /// otherwise the number of directions crossing them
+    fn tile_char(&self, heatmap: &Heatmap, x: usize, y: usize) -> char {
+        if let Some(c) = self.mirrors.get(&(x, y)) {
+            return *c;
+        }
+        let counts = heatmap.counts[y * self.maxx + x];
+        match counts.iter().filter(|c| **c > 0).count() {
+            0 => '.',
+            1 => ['^', '<', 'v', '>'][counts.iter().position(|c| *c > 0).unwrap()],
+            n => char::from_digit(n as u32, 10).unwrap(),
+        }
+    }
+
+    /// truecolor ANSI rendering, for a terminal
+    fn heatmap_to_ansi(&self, heatmap: &Heatmap) -> String {
+        let max = heatmap.max();
+        let mut ansi = String::new();
+        for y in 0..self.maxy {
+            for x in 0..self.maxx {
+                let c = self.tile_char(heatmap, x, y);
+                match heatmap.color(x, y, max) {
+                    Some((r, g, b)) => {
+                        let _ = write!(ansi, "\x1b[30;48;2;{r};{g};{b}m{c}\x1b[0m");
+                    }
+                    None => ansi.push(c),
                 }
             }
+            ansi.push('\n');
         }
+        ansi
+    }
 
-        let mut result = match dir.unwrap() {
-            North => {
-                if y == 0 {
-                    vec![]
-                } else {
-                    let mut result = match self.mirrors.get(&(x, y - 1)) {
-                        None => vec![Beam { x, y: y - 1, dir }],
-                        Some('|') => {
-                            if y > 1 {
-                                vec![Beam { x, y: y - 2, dir }]
-                            } else {
-                                vec![]
-                            }
-                        }
-                        Some('\\') => {
-                            if x > 0 {
-                                vec![Beam {
-                                    x: x - 1,
-                                    y: y - 1,
-                                    dir: Some(West),
-                                }]
-                            } else {
-                                vec![]
-                            }
-                        }
-                        Some('/') => {
-                            if x + 1 < self.maxx {
-                                vec![Beam {
-                                    x: x + 1,
-                                    y: y - 1,
-                                    dir: Some(East),
-                                }]
-                            } else {
-                                vec![]
-                            }
-                        }
-
-                        Some('-') => [
-                            if x > 0 {
-                                Some(Beam {
-                                    x: x - 1,
-                                    y: y - 1,
-                                    dir: Some(West),
-                                })
-                            } else {
-                                None
-                            },
-                            if x + 1 < self.maxx {
-                                Some(Beam {
-                                    x: x + 1,
-                                    y: y - 1,
-                                    dir: Some(East),
-                                })
-                            } else {
-                                None
-                            },
-                        ]
-                        .into_iter()
-                        .flatten()
-                        .collect(),
-                        Some(c) => panic!("unknown mirror {c} at ({x},{y})"),
-                    };
-                    result.push(Beam {
-                        x,
-                        y: y - 1,
-                        dir: None,
-                    });
-                    result
-                }
+    /// one `scale` pixels square by tile, components not crossed by any beam are grey
+    fn heatmap_to_png(&self, heatmap: &Heatmap, path: &str, scale: u32) -> Result<(), Error> {
+        let size = (self.maxx as u32 * scale, self.maxy as u32 * scale);
+        let root = BitMapBackend::new(path, size).into_drawing_area();
+        root.fill(&BLACK)?;
+        let max = heatmap.max();
+        for y in 0..self.maxy {
+            for x in 0..self.maxx {
+                let color = match heatmap.color(x, y, max) {
+                    Some((r, g, b)) => RGBColor(r, g, b),
+                    None if self.mirrors.contains_key(&(x, y)) => RGBColor(96, 96, 96),
+                    None => continue,
+                };
+                let (x, y) = ((x as u32 * scale) as i32, (y as u32 * scale) as i32);
+                let corners = [(x, y), (x + scale as i32, y + scale as i32)];
+                root.draw(&Rectangle::new(corners, color.filled()))?;
             }
-            West => {
-                if x == 0 {
-                    vec![]
-                } else {
-                    let mut result = match self.mirrors.get(&(x - 1, y)) {
-                        None => vec![Beam { x: x - 1, y, dir }],
-                        Some('-') => {
-                            if x > 1 {
-                                vec![Beam { x: x - 2, y, dir }]
-                            } else {
-                                vec![]
-                            }
-                        }
-                        Some('\\') => {
-                            if y > 0 {
-                                vec![Beam {
-                                    x: x - 1,
-                                    y: y - 1,
-                                    dir: Some(North),
-                                }]
-                            } else {
-                                vec![]
-                            }
-                        }
-                        Some('/') => {
-                            if y + 1 < self.maxy {
-                                vec![Beam {
-                                    x: x - 1,
-                                    y: y + 1,
-                                    dir: Some(South),
-                                }]
-                            } else {
-                                vec![]
-                            }
-                        }
-                        Some('|') => [
-                            if y > 0 {
-                                Some(Beam {
-                                    x: x - 1,
-                                    y: y - 1,
-                                    dir: Some(North),
-                                })
-                            } else {
-                                None
-                            },
-                            if y + 1 < self.maxy {
-                                Some(Beam {
-                                    x: x - 1,
-                                    y: y + 1,
-                                    dir: Some(South),
-                                })
-                            } else {
-                                None
-                            },
-                        ]
-                        .into_iter()
-                        .flatten()
-                        .collect(),
-                        Some(c) => panic!("unexpected mirror {c} at ({x},{y})"),
-                    };
-                    result.push(Beam {
-                        x: x - 1,
-                        y,
-                        dir: None,
-                    });
-                    result
+        }
+        root.present()?;
+        Ok(())
+    }
+    fn _print(&self) {
+        for y in 0..self.maxy {
+            println!(
+                "{}",
+                (0..self.maxx).map(|x| self.mirrors.get(&(x, y)).unwrap_or(&'.')).join("")
+            );
+        }
+    }
+    /// beams entering from each tile of the border, in every direction
+    fn edge_beams(&self) -> Vec<Beam> {
+        let vertical = [0, self.maxy - 1].into_iter().flat_map(|y| {
+            (0..self.maxx)
+                .flat_map(move |x| [North, South].into_iter().map(move |dir| Beam { x, y, dir }))
+        });
+        let horizontal = [0, self.maxx - 1].into_iter().flat_map(|x| {
+            (0..self.maxy)
+                .flat_map(move |y| [West, East].into_iter().map(move |dir| Beam { x, y, dir }))
+        });
+        vertical.chain(horizontal).collect()
+    }
+
+    fn state(&self, beam: Beam) -> usize {
+        (beam.y * self.maxx + beam.x) * 4 + beam.dir.index()
+    }
+
+    /// beam states are chained into segments, from a branching state (or an entry) to the next one,
+    /// then segments looping together are merged : their energized tiles are the same.
+    /// Where a beam goes after a rotating mirror depends on the previous hits : segments stop there,
+    /// and entries reaching one are left out of the graph
+    fn beam_graph(&self, entries: &[Beam]) -> BeamGraph {
+        let beams = (0..self.maxy).flat_map(|y| {
+            (0..self.maxx).flat_map(move |x| Direction::ALL.map(|dir| Beam { x, y, dir }))
+        });
+        let succ: Vec<Vec<usize>> = beams
+            .map(|b| {
+                if self.rotating.contains_key(&(b.x, b.y)) {
+                    return vec![];
                 }
+                self.propagate_step(b, 0).map(|n| self.state(n)).collect()
+            })
+            .collect();
+        let mut heads = vec![false; succ.len()];
+        let mut in_degrees = vec![0; succ.len()];
+        for next in &succ {
+            for n in next {
+                in_degrees[*n] += 1;
+                heads[*n] |= next.len() != 1;
             }
-            South => {
-                if y >= self.maxy - 1 {
-                    vec![]
-                } else {
-                    let mut result = match self.mirrors.get(&(x, y + 1)) {
-                        None => vec![Beam { x, y: y + 1, dir }],
-                        Some('|') => {
-                            if y + 2 < self.maxy {
-                                vec![Beam { x, y: y + 2, dir }]
-                            } else {
-                                vec![]
-                            }
-                        }
-                        Some('/') => {
-                            if x > 0 {
-                                vec![Beam {
-                                    x: x - 1,
-                                    y: y + 1,
-                                    dir: Some(West),
-                                }]
-                            } else {
-                                vec![]
-                            }
-                        }
-                        Some('\\') => {
-                            if x + 1 < self.maxx {
-                                vec![Beam {
-                                    x: x + 1,
-                                    y: y + 1,
-                                    dir: Some(East),
-                                }]
-                            } else {
-                                vec![]
-                            }
-                        }
-
-                        Some('-') => [
-                            if x > 0 {
-                                Some(Beam {
-                                    x: x - 1,
-                                    y: y + 1,
-                                    dir: Some(West),
-                                })
-                            } else {
-                                None
-                            },
-                            if x + 1 < self.maxx {
-                                Some(Beam {
-                                    x: x + 1,
-                                    y: y + 1,
-                                    dir: Some(East),
-                                })
-                            } else {
-                                None
-                            },
-                        ]
-                        .into_iter()
-                        .flatten()
-                        .collect(),
-                        Some(c) => panic!("unexpected mirror {c} at ({x},{y})"),
-                    };
-                    result.push(Beam {
-                        x,
-                        y: y + 1,
-                        dir: None,
-                    });
-                    result
+        }
+        for (head, in_degree) in heads.iter_mut().zip(&in_degrees) {
+            *head |= *in_degree != 1;
+        }
+        for b in entries {
+            heads[self.state(*b)] = true;
+        }
+
+        // segments reachable from the entries : their tiles, then the heads following them
+        let mut segment_ids: HashMap<usize, usize> = HashMap::new();
+        let mut segments: Vec<(Vec<usize>, Vec<usize>)> = vec![];
+        let mut on_rotating = vec![];
+        let mut to_visit: Vec<usize> = entries.iter().map(|b| self.state(*b)).collect();
+        while let Some(head) = to_visit.pop() {
+            if segment_ids.contains_key(&head) {
+                continue;
+            }
+            segment_ids.insert(head, segments.len());
+            let mut tiles = vec![head / 4];
+            let mut state = head;
+            while let [next] = succ[state][..] {
+                if heads[next] {
+                    break;
                 }
+                tiles.push(next / 4);
+                state = next;
             }
-            East => {
-                if x >= self.maxx - 1 {
-                    vec![]
-                } else {
-                    let mut result = match self.mirrors.get(&(x + 1, y)) {
-                        None => vec![Beam { x: x + 1, y, dir }],
-                        Some('-') => {
-                            if x + 2 < self.maxx {
-                                vec![Beam { x: x + 2, y, dir }]
-                            } else {
-                                vec![]
-                            }
-                        }
-                        Some('/') => {
-                            if y > 0 {
-                                vec![Beam {
-                                    x: x + 1,
-                                    y: y - 1,
-                                    dir: Some(North),
-                                }]
-                            } else {
-                                vec![]
-                            }
-                        }
-                        Some('\\') => {
-                            if y + 1 < self.maxy {
-                                vec![Beam {
-                                    x: x + 1,
-                                    y: y + 1,
-                                    dir: Some(South),
-                                }]
-                            } else {
-                                vec![]
-                            }
-                        }
-                        Some('|') => [
-                            if y > 0 {
-                                Some(Beam {
-                                    x: x + 1,
-                                    y: y - 1,
-                                    dir: Some(North),
-                                })
-                            } else {
-                                None
-                            },
-                            if y + 1 < self.maxy {
-                                Some(Beam {
-                                    x: x + 1,
-                                    y: y + 1,
-                                    dir: Some(South),
-                                })
-                            } else {
-                                None
-                            },
-                        ]
-                        .into_iter()
-                        .flatten()
-                        .collect(),
-                        Some(c) => panic!("unexpected mirror {c} at ({x},{y})"),
-                    };
-                    result.push(Beam {
-                        x: x + 1,
-                        y,
-                        dir: None,
-                    });
-                    result
+            to_visit.extend(&succ[state]);
+            let tile = state / 4;
+            on_rotating.push(self.rotating.contains_key(&(tile % self.maxx, tile / self.maxx)));
+            segments.push((tiles, succ[state].clone()));
+        }
+        let next_segments: Vec<Vec<usize>> = segments
+            .iter()
+            .map(|(_, next)| next.iter().map(|n| segment_ids[n]).collect())
+            .collect();
+
+        // components come out of tarjan after every component they lead to
+        let (components, count) = strongly_connected_components(&next_segments);
+        let mut energized = vec![Tiles::new(self.maxx * self.maxy); count];
+        let mut reaching_rotating = vec![false; count];
+        let mut by_component = vec![vec![]; count];
+        for (segment, component) in components.iter().enumerate() {
+            by_component[*component].push(segment);
+        }
+        for (component, segments_in) in by_component.iter().enumerate() {
+            let mut tiles = Tiles::new(self.maxx * self.maxy);
+            for segment in segments_in {
+                for tile in &segments[*segment].0 {
+                    tiles.set(*tile);
+                }
+                reaching_rotating[component] |= on_rotating[*segment];
+                for next in &next_segments[*segment] {
+                    if components[*next] != component {
+                        tiles.union_with(&energized[components[*next]]);
+                        reaching_rotating[component] |= reaching_rotating[components[*next]];
+                    }
                 }
             }
-        };
-        for b in result.iter().filter(|b| b.x >= self.maxx || b.y >= self.maxy) {
-            println!("Error on {b:?} from {x},{y},{dir:?}");
+            energized[component] = tiles;
         }
 
-        result.push(Beam { x, y, dir: None });
-        result
+        let entries = entries
+            .iter()
+            .map(|b| (*b, components[segment_ids[&self.state(*b)]]))
+            .filter(|(_, component)| !reaching_rotating[*component])
+            .collect();
+        BeamGraph { entries, energized }
     }
 
-    fn propagate(&self) -> HashSet<Beam> {
-        self.propagate_from_edge(Beam {
-            x: 0,
-            y: 0,
-            dir: Some(East),
-        })
+    fn edge_graph(&self) -> BeamGraph {
+        self.beam_graph(&self.edge_beams())
     }
-    fn propagate_from_edge(&self, beam: Beam) -> HashSet<Beam> {
-        let mut result: HashSet<Beam> = Default::default();
-        result.insert(beam);
 
-        let mut current_beams: Vec<Beam> = vec![beam];
+    /// the edge entry energizing the most tiles, with their count
+    fn best_entry(&self, graph: &BeamGraph) -> (Beam, usize) {
+        self.edge_beams()
+            .into_iter()
+            .map(|b| {
+                let count = graph.count_energized(&b);
+                (
+                    b,
+                    count.unwrap_or_else(|| count_energized(self.propagate_from_edge(b))),
+                )
+            })
+            .min_by_key(|(_, count)| Reverse(*count))
+            .unwrap()
+    }
 
-        while !current_beams.is_empty() {
-            // println!("current_beams {current_beams:?}");
-            current_beams = current_beams
-                .into_iter()
-                .flat_map(|b| self.propagate_step(b, false).into_iter())
-                .filter(|b| !result.contains(b))
-                .collect();
-            for b in &current_beams {
-                result.insert(*b);
+    fn tune(&self, graph: &BeamGraph) -> usize {
+        self.best_entry(graph).1
+    }
+}
+
+/// tarjan, without recursion : the component of each node, and the number of components,
+/// numbered in reverse topological order
+fn strongly_connected_components(next: &[Vec<usize>]) -> (Vec<usize>, usize) {
+    let mut indices: Vec<Option<usize>> = vec![None; next.len()];
+    let mut lows = vec![0; next.len()];
+    let mut on_stack = vec![false; next.len()];
+    let mut stack = vec![];
+    let mut components = vec![0; next.len()];
+    let mut count = 0;
+    let mut index = 0;
+    for root in 0..next.len() {
+        if indices[root].is_some() {
+            continue;
+        }
+        let mut calls = vec![(root, 0)];
+        indices[root] = Some(index);
+        lows[root] = index;
+        index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        while let Some((node, child)) = calls.last_mut() {
+            let node = *node;
+            if let Some(n) = next[node].get(*child) {
+                *child += 1;
+                match indices[*n] {
+                    None => {
+                        indices[*n] = Some(index);
+                        lows[*n] = index;
+                        index += 1;
+                        stack.push(*n);
+                        on_stack[*n] = true;
+                        calls.push((*n, 0));
+                    }
+                    Some(i) if on_stack[*n] => lows[node] = lows[node].min(i),
+                    Some(_) => {}
+                }
+                continue;
+            }
+            calls.pop();
+            if let Some((caller, _)) = calls.last() {
+                lows[*caller] = lows[*caller].min(lows[node]);
+            }
+            if Some(lows[node]) == indices[node] {
+                while let Some(n) = stack.pop() {
+                    on_stack[n] = false;
+                    components[n] = count;
+                    if n == node {
+                        break;
+                    }
+                }
+                count += 1;
             }
         }
+    }
+    (components, count)
+}
+
+/// bitset of tiles, by y * maxx + x
+#[derive(Debug, Clone)]
+struct Tiles(Vec<u64>);
 
-        // self._display_beam(&result);
-        result
+impl Tiles {
+    fn new(len: usize) -> Self {
+        Self(vec![0; len.div_ceil(64)])
     }
-    fn _display_beam(&self, beams: &HashSet<Beam>) {
-        let beam_points: HashSet<(usize, usize)> = beams.iter().map(|b| (b.x, b.y)).collect();
-        assert!(beam_points.iter().all(|(x, y)| *x < self.maxx && *y < self.maxy));
-        for y in 0..self.maxy {
-            println!(
-                "{}",
-                (0..self.maxx)
-                    .map(|x| if beam_points.contains(&(x, y)) {
-                        &'#'
-                    } else {
-                        self.mirrors.get(&(x, y)).unwrap_or(&'.')
-                    })
-                    .join("")
-            );
+
+    fn set(&mut self, i: usize) {
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+
+    fn union_with(&mut self, other: &Tiles) {
+        for (w, o) in self.0.iter_mut().zip(&other.0) {
+            *w |= o;
         }
     }
-    fn _print(&self) {
-        for y in 0..self.maxy {
-            println!(
-                "{}",
-                (0..self.maxx).map(|x| self.mirrors.get(&(x, y)).unwrap_or(&'.')).join("")
-            );
+
+    fn count(&self) -> usize {
+        self.0.iter().map(|w| w.count_ones() as usize).sum()
+    }
+}
+
+/// energized tiles for a set of entries, shared between all the entries going through the same beams
+struct BeamGraph {
+    /// component of the segment starting at each entry
+    entries: HashMap<Beam, usize>,
+    /// tiles energized from each component
+    energized: Vec<Tiles>,
+}
+
+impl BeamGraph {
+    /// None if the beam was not an entry of the graph, or reaches a rotating mirror
+    fn count_energized(&self, entry: &Beam) -> Option<usize> {
+        self.entries.get(entry).map(|c| self.energized[*c].count())
+    }
+}
+
+/// beams passing through each tile, counted by direction
+#[derive(Debug, Clone)]
+struct Heatmap {
+    maxx: usize,
+    /// by tile, y * maxx + x, then by direction index
+    counts: Vec<[usize; 4]>,
+}
+
+impl Heatmap {
+    fn add(&mut self, beams: &HashSet<Beam>) {
+        for b in beams {
+            self.counts[b.y * self.maxx + b.x][b.dir.index()] += 1;
         }
     }
-    fn tune(&self) -> usize {
-        max(
-            [0, self.maxy - 1]
-                .into_iter()
-                .flat_map(|y| {
-                    (0..self.maxx).flat_map(move |x| {
-                        [North, South].into_iter().map(move |dir| Beam {
-                            x,
-                            y,
-                            dir: Some(dir),
-                        })
-                    })
-                })
-                .collect_vec()
-                .into_par_iter()
-                .map(|b| count_energized(self.propagate_from_edge(b)))
-                .max()
-                .unwrap(),
-            [0, self.maxx - 1]
-                .into_iter()
-                .flat_map(|x| {
-                    (0..self.maxy).flat_map(move |y| {
-                        [West, East].into_iter().map(move |dir| Beam {
-                            x,
-                            y,
-                            dir: Some(dir),
-                        })
-                    })
-                })
-                .map(|b| count_energized(self.propagate_from_edge(b)))
-                .max()
-                .unwrap(),
-        )
+
+    fn count(&self, x: usize, y: usize) -> usize {
+        self.counts[y * self.maxx + x].iter().sum()
+    }
+
+    fn max(&self) -> usize {
+        self.counts.iter().map(|c| c.iter().sum()).max().unwrap_or(0)
+    }
+
+    /// from dark red to white as the count gets close to `max`, None if no beam crosses the tile;
+    /// renderers compute the max once, it's a scan of the whole heatmap
+    fn color(&self, x: usize, y: usize, max: usize) -> Option<(u8, u8, u8)> {
+        let count = self.count(x, y);
+        if count == 0 {
+            return None;
+        }
+        let heat = 0.2 + 0.8 * count as f64 / max as f64;
+        let channel = |offset: f64| ((3. * heat - offset).clamp(0., 1.) * 255.).round() as u8;
+        Some((channel(0.), channel(1.), channel(2.)))
     }
 }
 
@@ -523,13 +559,27 @@ fn count_energized(beams: HashSet<Beam>) -> usize {
     beams.into_iter().map(|b| (b.x, b.y)).unique().count()
 }
 
-pub fn fix_contraption() {
+pub fn fix_contraption(export: bool) {
     let contraption: Contraption =
         include_str!("../resources/day16_contraption.txt").parse().unwrap();
     let energized = count_energized(contraption.propagate());
     println!("energized cells : {energized}");
-    let max_energized = contraption.tune();
+    let graph = contraption.edge_graph();
+    let max_energized = contraption.tune(&graph);
     println!("tuned contraption : {max_energized} energized cells");
+
+    let (best, _) = contraption.best_entry(&graph);
+    println!("best entry {best:?}");
+    if export {
+        let heatmap = contraption.heatmap(&[best]);
+        std::fs::write(".day16_heatmap.ansi", contraption.heatmap_to_ansi(&heatmap)).unwrap();
+        contraption.heatmap_to_png(&heatmap, ".day16_heatmap.png", 8).unwrap();
+        let all_entries = contraption.heatmap(&contraption.edge_beams());
+        contraption.heatmap_to_png(&all_entries, ".day16_heatmap_all.png", 8).unwrap();
+        println!(
+            "heatmaps written to .day16_heatmap.ansi, .day16_heatmap.png and .day16_heatmap_all.png"
+        );
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -553,6 +603,168 @@ mod tests {
         contraption._print();
         println!();
         assert_eq!(46, count_energized(contraption.propagate()));
-        assert_eq!(51, contraption.tune());
+        assert_eq!(51, contraption.tune(&contraption.edge_graph()));
+    }
+
+    #[test]
+    fn components_works() {
+        let energized = |input: &str, x, y, dir| {
+            let contraption: Contraption = input.parse().unwrap();
+            count_energized(contraption.propagate_from_edge(Beam { x, y, dir }))
+        };
+        // absorber
+        assert_eq!(3, energized("..#..", 0, 0, East));
+        // one-way mirror, reflecting eastward beams only
+        let one_way = ".....\n..>..\n.....\n";
+        assert_eq!(4, energized(one_way, 0, 1, East));
+        assert_eq!(5, energized(one_way, 4, 1, West));
+        // three-way splitter
+        assert_eq!(5, energized("...\n.+.\n...\n", 0, 1, East));
+        // clockwise turners, the beam loops around the center
+        assert_eq!(8, energized("r.r\n...\nr.r\n", 1, 0, East));
+        // the beam goes around once, the mirror having turned it leaves eastward
+        assert_eq!(8, energized("/.\\.\n....\n\\./.\n", 2, 1, South));
+        assert_eq!(9, energized("/.\\.\n....\n\\.@.\n", 2, 1, South));
+        assert_eq!(2, Components::default().0[&'@'].0.len());
+
+        let components: Components = ".  N:N W:W S:S E:E\n// absorbs all\n*".parse().unwrap();
+        let contraption = Contraption::parse("..*..", components.clone()).unwrap();
+        assert_eq!(3, count_energized(contraption.propagate()));
+        assert!(Contraption::parse("..|..", components).is_err());
+
+        assert!("x N:Q".parse::<Components>().is_err());
+        assert!(". NS:N".parse::<Components>().is_err());
+        assert!(".\n| N:N\n| S:S".parse::<Components>().is_err());
+        assert!("| N:N".parse::<Components>().is_err());
+    }
+
+    #[test]
+    fn heatmap_works() {
+        let contraption: Contraption = ".|.\n...\n".parse().unwrap();
+        let entry = Beam {
+            x: 0,
+            y: 0,
+            dir: East,
+        };
+        let heatmap = contraption.heatmap(&[entry]);
+        assert_eq!([0, 0, 0, 1], heatmap.counts[0]);
+        assert_eq!([0, 0, 1, 0], heatmap.counts[4]);
+        assert_eq!(None, heatmap.color(2, 0, heatmap.max()));
+        assert_eq!(Some((255, 255, 255)), heatmap.color(1, 1, heatmap.max()));
+        let white = |c| format!("\x1b[30;48;2;255;255;255m{c}\x1b[0m");
+        assert_eq!(
+            format!("{}{}.\n.{}.\n", white('>'), white('|'), white('v')),
+            contraption.heatmap_to_ansi(&heatmap)
+        );
+
+        // entering twice from the top left, once from the top right, then once from the bottom left
+        let heatmap = contraption.heatmap(&[
+            entry,
+            entry,
+            Beam {
+                x: 2,
+                y: 0,
+                dir: West,
+            },
+            Beam {
+                x: 0,
+                y: 1,
+                dir: East,
+            },
+        ]);
+        assert_eq!([0, 0, 0, 2], heatmap.counts[0]);
+        assert_eq!([0, 0, 3, 1], heatmap.counts[4]);
+        assert_eq!(4, heatmap.max());
+        assert_eq!('2', contraption.tile_char(&heatmap, 1, 1));
+        assert_eq!('<', contraption.tile_char(&heatmap, 2, 0));
+        // 0.2 + 0.8 / 4 of the heat
+        assert_eq!(Some((255, 51, 0)), heatmap.color(2, 0, heatmap.max()));
+
+        let contraption: Contraption = indoc! {r"
+            .|...\....
+            |.-.\.....
+            .....|-...
+            ........|.
+            ..........
+            .........\
+            ..../.\\..
+            .-.-/..|..
+            .|....-|.\
+            ..//.|....
+        "}
+        .parse()
+        .unwrap();
+        let (best, count) = contraption.best_entry(&contraption.edge_graph());
+        assert_eq!(
+            (
+                Beam {
+                    x: 3,
+                    y: 0,
+                    dir: South
+                },
+                51
+            ),
+            (best, count)
+        );
+        let heatmap = contraption.heatmap(&[best]);
+        assert_eq!(
+            51,
+            heatmap.counts.iter().filter(|c| c.iter().sum::<usize>() > 0).count()
+        );
+    }
+
+    #[test]
+    fn beam_graph_works() {
+        for input in [
+            indoc! {r"
+                .|...\....
+                |.-.\.....
+                .....|-...
+                ........|.
+                ..........
+                .........\
+                ..../.\\..
+                .-.-/..|..
+                .|....-|.\
+                ..//.|....
+            "},
+            // loops, absorbers and three-way splitters
+            indoc! {r"
+                r.r..+..
+                ..#.....
+                r.r.-..|
+                ...+..<.
+                .>....l.
+            "},
+            // rotating mirrors, out of the graph
+            indoc! {r"
+                /..\.@..
+                ..@..|..
+                \..@/.-.
+                ........
+            "},
+        ] {
+            let contraption: Contraption = input.parse().unwrap();
+            let entries = contraption.edge_beams();
+            let graph = contraption.beam_graph(&entries);
+            let counts = entries
+                .iter()
+                .map(|b| count_energized(contraption.propagate_from_edge(*b)))
+                .collect_vec();
+            for (b, count) in entries.iter().zip(&counts) {
+                let reaches_rotating = contraption
+                    .propagate_from_edge(*b)
+                    .iter()
+                    .any(|b| contraption.rotating.contains_key(&(b.x, b.y)));
+                let expected = (!reaches_rotating).then_some(*count);
+                assert_eq!(expected, graph.count_energized(b), "from {b:?}");
+            }
+            assert_eq!(counts.iter().max(), Some(&contraption.tune(&graph)));
+        }
+
+        assert_eq!(
+            (vec![1, 1, 0, 2], 3),
+            strongly_connected_components(&[vec![1], vec![0, 2], vec![], vec![3, 0]])
+        );
     }
 }
@@ -1,6 +1,5 @@
-use ahash::AHashMap;
-use itertools::Itertools;
-use std::cmp::min;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::fmt::{Display, Formatter, Write};
 use std::str::FromStr;
 
@@ -10,8 +9,133 @@ enum Dir {
     West,
     South,
     East,
+    NorthWest,
+    NorthEast,
+    SouthWest,
+    SouthEast,
     StartStop,
 }
+
+impl Dir {
+    const ORTHOGONAL: [Dir; 4] = [Dir::North, Dir::West, Dir::South, Dir::East];
+    const DIAGONAL: [Dir; 4] = [
+        Dir::NorthWest,
+        Dir::NorthEast,
+        Dir::SouthWest,
+        Dir::SouthEast,
+    ];
+
+    fn delta(self) -> (isize, isize) {
+        match self {
+            Dir::North => (0, -1),
+            Dir::West => (-1, 0),
+            Dir::South => (0, 1),
+            Dir::East => (1, 0),
+            Dir::NorthWest => (-1, -1),
+            Dir::NorthEast => (1, -1),
+            Dir::SouthWest => (-1, 1),
+            Dir::SouthEast => (1, 1),
+            Dir::StartStop => (0, 0),
+        }
+    }
+
+    fn is_opposite(self, other: Dir) -> bool {
+        let ((dx, dy), (ox, oy)) = (self.delta(), other.delta());
+        self != Dir::StartStop && (dx, dy) == (-ox, -oy)
+    }
+}
+
+/// movement rules of a crucible
+#[derive(Debug, Clone)]
+struct Crucible {
+    /// allowed directions, with the maximum straight run toward each of them
+    max_runs: Vec<(Dir, usize)>,
+    /// straight run needed before turning or stopping
+    min_run: usize,
+    /// going back the way it came
+    u_turns: bool,
+    /// extra heat loss for each change of direction
+    turn_penalty: usize,
+}
+
+impl Crucible {
+    fn new(min_run: usize, max_run: usize) -> Self {
+        Self {
+            max_runs: Dir::ORTHOGONAL.iter().map(|d| (*d, max_run)).collect(),
+            min_run,
+            u_turns: false,
+            turn_penalty: 0,
+        }
+    }
+
+    fn standard() -> Self {
+        Self::new(1, 3)
+    }
+
+    fn ultra() -> Self {
+        Self::new(4, 10)
+    }
+
+    fn with_u_turns(mut self) -> Self {
+        self.u_turns = true;
+        self
+    }
+
+    /// diagonal moves, with the same maximum run as orthogonal ones
+    fn with_diagonals(mut self) -> Self {
+        let max_run = self.max_runs.iter().map(|(_, r)| *r).max().unwrap_or(0);
+        self.max_runs.extend(Dir::DIAGONAL.iter().map(|d| (*d, max_run)));
+        self
+    }
+
+    fn with_turn_penalty(mut self, turn_penalty: usize) -> Self {
+        self.turn_penalty = turn_penalty;
+        self
+    }
+
+    /// 0 forbids the direction
+    fn with_max_run(mut self, dir: Dir, max_run: usize) -> Self {
+        self.max_runs.retain(|(d, _)| *d != dir);
+        if max_run > 0 {
+            self.max_runs.push((dir, max_run));
+        }
+        self
+    }
+
+    fn can_stop(&self, current: &Move) -> bool {
+        current.l >= self.min_run
+    }
+
+    /// moves within the map, with their extra heat loss besides the one of the reached block
+    fn next_moves(
+        &self,
+        maxx: usize,
+        maxy: usize,
+        current: Move,
+    ) -> impl Iterator<Item = (Move, usize)> + '_ {
+        let Move { x, y, dir, l } = current;
+        self.max_runs.iter().filter_map(move |(to, max_run)| {
+            let (l, penalty) = if dir == Dir::StartStop {
+                (1, 0)
+            } else if dir == *to {
+                if l >= *max_run {
+                    return None;
+                }
+                (l + 1, 0)
+            } else {
+                if l < self.min_run || (!self.u_turns && dir.is_opposite(*to)) {
+                    return None;
+                }
+                (1, self.turn_penalty)
+            };
+            let (dx, dy) = to.delta();
+            let x = x.checked_add_signed(dx).filter(|x| *x < maxx)?;
+            let y = y.checked_add_signed(dy).filter(|y| *y < maxy)?;
+            Some((Move { x, y, dir: *to, l }, penalty))
+        })
+    }
+}
+
 struct HeatLossMap(Vec<Vec<usize>>);
 
 impl FromStr for HeatLossMap {
@@ -45,224 +169,140 @@ struct Move {
     l: usize,
 }
 
-// fn get_next_moves(heatloss_map: &[Vec<usize>], current:Move) -> [Option<Move>;3]{
-//     let maxx = heatloss_map[0].len();
-//     let maxy= heatloss_map.len();
-//     let Move{x,y,dir,l}=current;
-//     match dir {
-//         Dir::North => {[if l < 3 && y >0 {Some(Move{x,y:y-1,dir:Dir::North,l:l+1})}else{None},
-//                         if x >0 {Some(Move{x:x-1,y,dir:Dir::West,l:1})}else{None},
-//                         if x+1<maxx {Some(Move{x:x+1,y,dir:Dir::East,l:1})}else { None }]}
-//         Dir::West => {
-//             [if l < 3 && x >0 {Some(Move{x:x-1,y,dir:Dir::West,l:l+1})}else{None},
-//                 if y >0 {Some(Move{x,y:y-1,dir:Dir::North,l:1})}else{None},
-//                 if y+1<maxy {Some(Move{x,y:y+1,dir:Dir::South,l:1})}else { None }]
-//         }
-//         Dir::South => {[if l < 3 && y+1 <maxy {Some(Move{x,y:y+1,dir:Dir::South,l:l+1})}else{None},
-//                         if x >0 {Some(Move{x:x-1,y,dir:Dir::West,l:1})}else{None},
-//                         if x+1<maxx {Some(Move{x:x+1,y,dir:Dir::East,l:1})}else { None }]}
-//         Dir::East => {
-//             [if l < 3 && x+1 < maxx {Some(Move{x:x+1,y,dir:Dir::East,l:l+1})}else{None},
-//                 if y >0 {Some(Move{x,y:y-1,dir:Dir::North,l:1})}else{None},
-//                 if y+1<maxy {Some(Move{x,y:y+1,dir:Dir::South,l:1})}else { None }]
-//
-//
-//         }
-//         Dir::StartStop => {if current.x==0 && current.y==0 {[Some(Move{x:1,y:0,dir:Dir::East,l:1}),Some(Move{x:0,y:1,dir:Dir::South,l:1}),None]}else { panic!("None move on {current:?}") }}
-//     }
-// }
-
-fn get_next_moves(
-    heatloss_map: &[Vec<usize>],
-    current: Move,
-    min_len: usize,
-    max_len: usize,
-) -> [Option<Move>; 3] {
+/// lowest heat loss from each block to the bottom right one, moving as the crucible does
+/// but ignoring its run limits and turn penalties : this never overestimates its actual loss.
+/// usize::MAX for blocks from which the bottom right one cannot be reached
+fn losses_to_end(heatloss_map: &[Vec<usize>], crucible: &Crucible) -> Vec<usize> {
     let maxx = heatloss_map[0].len();
     let maxy = heatloss_map.len();
-    let Move { x, y, dir, l } = current;
-    match dir {
-        Dir::North => [
-            (l < max_len && y > 0).then_some(Move {
-                x,
-                y: y - 1,
-                dir: Dir::North,
-                l: l + 1,
-            }),
-            (l >= min_len && x > 0).then_some(Move {
-                x: x - 1,
-                y,
-                dir: Dir::West,
-                l: 1,
-            }),
-            (l >= min_len && x + 1 < maxx).then_some(Move {
-                x: x + 1,
-                y,
-                dir: Dir::East,
-                l: 1,
-            }),
-        ],
-        Dir::West => [
-            (l < max_len && x > 0).then_some(Move {
-                x: x - 1,
-                y,
-                dir: Dir::West,
-                l: l + 1,
-            }),
-            (l >= min_len && y > 0).then_some(Move {
-                x,
-                y: y - 1,
-                dir: Dir::North,
-                l: 1,
-            }),
-            (l >= min_len && y + 1 < maxy).then_some(Move {
-                x,
-                y: y + 1,
-                dir: Dir::South,
-                l: 1,
-            }),
-        ],
-        Dir::South => [
-            (l < max_len && y + 1 < maxy).then_some(Move {
-                x,
-                y: y + 1,
-                dir: Dir::South,
-                l: l + 1,
-            }),
-            (l >= min_len && x > 0).then_some(Move {
-                x: x - 1,
-                y,
-                dir: Dir::West,
-                l: 1,
-            }),
-            (l >= min_len && x + 1 < maxx).then_some(Move {
-                x: x + 1,
-                y,
-                dir: Dir::East,
-                l: 1,
-            }),
-        ],
-        Dir::East => [
-            (l < max_len && x + 1 < maxx).then_some(Move {
-                x: x + 1,
-                y,
-                dir: Dir::East,
-                l: l + 1,
-            }),
-            (l >= min_len && y > 0).then_some(Move {
-                x,
-                y: y - 1,
-                dir: Dir::North,
-                l: 1,
-            }),
-            (l >= min_len && y + 1 < maxy).then_some(Move {
-                x,
-                y: y + 1,
-                dir: Dir::South,
-                l: 1,
-            }),
-        ],
-        Dir::StartStop => {
-            if current.x == 0 && current.y == 0 {
-                [
-                    Some(Move {
-                        x: 1,
-                        y: 0,
-                        dir: Dir::East,
-                        l: 1,
-                    }),
-                    Some(Move {
-                        x: 0,
-                        y: 1,
-                        dir: Dir::South,
-                        l: 1,
-                    }),
-                    None,
-                ]
-            } else {
-                panic!("None move on {current:?}")
+    let mut losses = vec![usize::MAX; maxx * maxy];
+    let mut heap = BinaryHeap::new();
+    losses[maxx * maxy - 1] = 0;
+    heap.push(Reverse((0, maxx - 1, maxy - 1)));
+    while let Some(Reverse((loss, x, y))) = heap.pop() {
+        if loss > losses[y * maxx + x] {
+            continue;
+        }
+        // blocks moving to (x, y), whose loss is paid on entering it
+        let loss = loss + heatloss_map[y][x];
+        for (dir, _) in &crucible.max_runs {
+            let (dx, dy) = dir.delta();
+            let Some(px) = x.checked_add_signed(-dx).filter(|x| *x < maxx) else {
+                continue;
+            };
+            let Some(py) = y.checked_add_signed(-dy).filter(|y| *y < maxy) else {
+                continue;
+            };
+            if loss < losses[py * maxx + px] {
+                losses[py * maxx + px] = loss;
+                heap.push(Reverse((loss, px, py)));
             }
         }
     }
+    losses
 }
 
-fn get_minimal_heat_loss(heatloss_map: &[Vec<usize>], min_len: usize, max_len: usize) -> usize {
+/// Dijkstra over a dense array of states, guided toward the bottom right block if `guided` (A*),
+/// by the losses to the end of the crucible once freed from its run limits.
+/// None if the crucible cannot reach the bottom right block
+fn search(heatloss_map: &[Vec<usize>], crucible: &Crucible, guided: bool) -> Option<usize> {
     let maxx = heatloss_map[0].len();
     let maxy = heatloss_map.len();
-    let end: Move = Move {
-        x: maxx - 1,
-        y: maxy - 1,
-        dir: Dir::StartStop,
-        l: 42,
+    let max_run = crucible.max_runs.iter().map(|(_, r)| *r).max().unwrap_or(0);
+    let dirs = crucible.max_runs.len();
+    let mut slots = [0; 9];
+    for (slot, (dir, _)) in crucible.max_runs.iter().enumerate() {
+        slots[*dir as usize] = slot;
+    }
+    let index =
+        |m: &Move| ((m.y * maxx + m.x) * dirs + slots[m.dir as usize]) * (max_run + 1) + m.l;
+    let losses_to_end = if guided {
+        losses_to_end(heatloss_map, crucible)
+    } else {
+        vec![0; maxx * maxy]
     };
+    let heuristic = |m: &Move| losses_to_end[m.y * maxx + m.x];
 
-    let mut moves_to_minimal_loss: AHashMap<Move, usize> = AHashMap::with_capacity(maxx * maxy * 4);
-
-    let mut current_moves: Vec<_> = get_next_moves(
-        heatloss_map,
+    let state = |i: usize| {
+        let (rest, l) = (i / (max_run + 1), i % (max_run + 1));
+        let (cell, slot) = (rest / dirs, rest % dirs);
+        let dir = crucible.max_runs[slot].0;
         Move {
-            x: 0,
-            y: 0,
-            dir: Dir::StartStop,
-            l: 42,
-        },
-        min_len,
-        max_len,
-    )
-    .into_iter()
-    .flatten()
-    .collect();
-    for m in &current_moves {
-        moves_to_minimal_loss.insert(*m, heatloss_map[m.y][m.x]);
-    }
-    while !current_moves.is_empty() {
-        let mut new_moves = Vec::with_capacity(current_moves.len());
-        for current_m in &current_moves {
-            let current_loss = moves_to_minimal_loss.get(current_m).copied().unwrap();
-            for next_m in
-                get_next_moves(heatloss_map, *current_m, min_len, max_len).iter().flatten()
-            {
-                let loss = current_loss + heatloss_map[next_m.y][next_m.x];
-                if next_m.x == maxx - 1 && next_m.y == maxy - 1 {
-                    if next_m.l < min_len {
-                        continue;
-                    }
-                    moves_to_minimal_loss
-                        .entry(end)
-                        .and_modify(|l| *l = min(*l, loss))
-                        .or_insert(loss);
-                    // goal reached , no need to get next step
-                } else {
-                    if let Some(l) = moves_to_minimal_loss.get(next_m) {
-                        if *l <= loss {
-                            // a better move is already registered
-                            continue;
-                        }
-                    }
-                    moves_to_minimal_loss
-                        .entry(*next_m)
-                        .and_modify(|l| *l = min(*l, loss))
-                        .or_insert(loss);
-                    new_moves.push(*next_m);
-                }
+            x: cell % maxx,
+            y: cell / maxx,
+            dir,
+            l,
+        }
+    };
+
+    // heap entries are kept small : (estimated loss, state index)
+    let mut losses = vec![usize::MAX; maxx * maxy * dirs * (max_run + 1)];
+    let mut heap = BinaryHeap::new();
+    let relax = |heap: &mut BinaryHeap<_>, losses: &mut [usize], from: Move, loss: usize| {
+        for (next_m, penalty) in crucible.next_moves(maxx, maxy, from) {
+            if next_m.x == maxx - 1 && next_m.y == maxy - 1 && !crucible.can_stop(&next_m) {
+                continue;
+            }
+            if heuristic(&next_m) == usize::MAX {
+                continue;
+            }
+            let next_loss = loss + heatloss_map[next_m.y][next_m.x] + penalty;
+            let i = index(&next_m);
+            if next_loss < losses[i] {
+                losses[i] = next_loss;
+                heap.push(Reverse((next_loss + heuristic(&next_m), i)));
             }
         }
-        current_moves = new_moves.into_iter().unique().collect();
+    };
+    let start = Move {
+        x: 0,
+        y: 0,
+        dir: Dir::StartStop,
+        l: 42,
+    };
+    relax(&mut heap, &mut losses, start, 0);
+    while let Some(Reverse((estimate, i))) = heap.pop() {
+        let current = state(i);
+        let loss = losses[i];
+        if estimate > loss + heuristic(&current) {
+            // a better path to this state was found since it was queued
+            continue;
+        }
+        if current.x == maxx - 1 && current.y == maxy - 1 {
+            return Some(loss);
+        }
+        relax(&mut heap, &mut losses, current, loss);
     }
+    None
+}
 
-    *moves_to_minimal_loss.get(&end).unwrap()
+/// None if the crucible cannot reach the bottom right block
+fn get_minimal_heat_loss(heatloss_map: &[Vec<usize>], crucible: &Crucible) -> Option<usize> {
+    search(heatloss_map, crucible, true)
 }
+
 pub fn carry_lava() {
     let heatloss_map: HeatLossMap =
         include_str!("../resources/day17_heatloss.txt").parse().unwrap();
-    let minimum_loss = get_minimal_heat_loss(&heatloss_map.0, 1, 3);
+    let minimum_loss = get_minimal_heat_loss(&heatloss_map.0, &Crucible::standard()).unwrap();
     println!("minimum los {minimum_loss}");
-    let minimum_loss_ultra = get_minimal_heat_loss(&heatloss_map.0, 4, 10);
+    let minimum_loss_ultra = get_minimal_heat_loss(&heatloss_map.0, &Crucible::ultra()).unwrap();
     println!("minimum los {minimum_loss_ultra}");
+    let agile = Crucible::standard()
+        .with_diagonals()
+        .with_u_turns()
+        .with_turn_penalty(2)
+        .with_max_run(Dir::North, 0)
+        .with_max_run(Dir::West, 0);
+    let minimum_loss_agile = get_minimal_heat_loss(&heatloss_map.0, &agile).unwrap();
+    println!(
+        "minimum los with diagonals, u-turns, turn penalty, no north nor west moves {minimum_loss_agile}"
+    );
 }
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::random_grid;
     use indoc::indoc;
     #[test]
     fn aoc_examples_works() {
@@ -274,7 +314,10 @@ mod tests {
         .parse()
         .unwrap();
         println!("{heat_map}\n");
-        assert_eq!(5, get_minimal_heat_loss(&heat_map.0, 1, 3));
+        assert_eq!(
+            5,
+            get_minimal_heat_loss(&heat_map.0, &Crucible::standard()).unwrap()
+        );
 
         let heat_map: HeatLossMap = indoc! {"
             2413432311323
@@ -294,8 +337,14 @@ mod tests {
         .parse()
         .unwrap();
         println!("{heat_map}\n");
-        assert_eq!(102, get_minimal_heat_loss(&heat_map.0, 1, 3));
-        assert_eq!(94, get_minimal_heat_loss(&heat_map.0, 4, 10));
+        assert_eq!(
+            102,
+            get_minimal_heat_loss(&heat_map.0, &Crucible::standard()).unwrap()
+        );
+        assert_eq!(
+            94,
+            get_minimal_heat_loss(&heat_map.0, &Crucible::ultra()).unwrap()
+        );
         let heat_map: HeatLossMap = indoc! {"
             111111111111
             999999999991
@@ -305,6 +354,106 @@ mod tests {
         "}
         .parse()
         .unwrap();
-        assert_eq!(71, get_minimal_heat_loss(&heat_map.0, 4, 10));
+        assert_eq!(
+            71,
+            get_minimal_heat_loss(&heat_map.0, &Crucible::ultra()).unwrap()
+        );
+    }
+
+    #[test]
+    fn movement_rules_works() {
+        let ones: HeatLossMap = "1111\n1111\n1111\n".parse().unwrap();
+        let loss = |map: &HeatLossMap, crucible| get_minimal_heat_loss(&map.0, &crucible);
+        // east east east, then south south : a single turn
+        assert_eq!(
+            Some(15),
+            loss(&ones, Crucible::standard().with_turn_penalty(10))
+        );
+        // south east, south east, east
+        assert_eq!(Some(3), loss(&ones, Crucible::standard().with_diagonals()));
+        // east south east south east : 4 turns
+        let one_step_east = Crucible::standard().with_max_run(Dir::East, 1).with_turn_penalty(1);
+        assert_eq!(Some(9), loss(&ones, one_step_east));
+        assert_eq!(
+            None,
+            loss(&ones, Crucible::standard().with_max_run(Dir::East, 0))
+        );
+
+        // east east, back west, then east east
+        let line: HeatLossMap = "1234\n".parse().unwrap();
+        assert_eq!(None, loss(&line, Crucible::new(1, 2)));
+        assert_eq!(Some(14), loss(&line, Crucible::new(1, 2).with_u_turns()));
+    }
+
+    /// pseudo random heat losses, from 1 to 9
+    fn generate_map(size: usize, seed: u64) -> Vec<Vec<usize>> {
+        random_grid(size, size, seed, |r| 1 + (r >> 33) as usize % 9)
+    }
+
+    fn crucibles() -> [(&'static str, Crucible); 4] {
+        [
+            ("standard", Crucible::standard()),
+            ("ultra", Crucible::ultra()),
+            ("diagonal", Crucible::standard().with_diagonals()),
+            (
+                "agile",
+                Crucible::standard()
+                    .with_diagonals()
+                    .with_u_turns()
+                    .with_turn_penalty(2)
+                    .with_max_run(Dir::North, 0)
+                    .with_max_run(Dir::West, 0),
+            ),
+        ]
+    }
+
+    #[test]
+    fn guided_search_works() {
+        for size in [1, 2, 17, 40] {
+            let map = generate_map(size, size as u64);
+            for (name, crucible) in crucibles() {
+                assert_eq!(
+                    search(&map, &crucible, false),
+                    search(&map, &crucible, true),
+                    "{name} crucible on {size}x{size}"
+                );
+            }
+        }
+        // the bottom right block is behind a wall of 9s : the guide makes no difference
+        let walled: HeatLossMap = "1119\n1119\n9999\n9991\n".parse().unwrap();
+        for (name, crucible) in crucibles() {
+            assert_eq!(
+                search(&walled.0, &crucible, false),
+                search(&walled.0, &crucible, true),
+                "{name} crucible"
+            );
+        }
+        let losses = losses_to_end(&walled.0, &Crucible::standard());
+        assert_eq!((22, 0), (losses[0], losses[15]));
+        // never going east, only the last column can reach the bottom right block
+        let losses = losses_to_end(&walled.0, &Crucible::standard().with_max_run(Dir::East, 0));
+        assert_eq!((usize::MAX, 19), (losses[0], losses[3]));
+    }
+
+    /// benchmark : run with --release --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn large_maps_works() {
+        for size in [150, 300, 500] {
+            let map = generate_map(size, size as u64);
+            for (name, crucible) in crucibles() {
+                let start = std::time::Instant::now();
+                let dijkstra = search(&map, &crucible, false);
+                let dijkstra_time = start.elapsed();
+                let start = std::time::Instant::now();
+                let a_star = search(&map, &crucible, true);
+                let a_star_time = start.elapsed();
+                assert_eq!(dijkstra, a_star, "{name} crucible on {size}x{size}");
+                assert!(dijkstra.is_some());
+                println!(
+                    "{size}x{size} {name} crucible : {dijkstra:?}, dijkstra {dijkstra_time:?}, a* {a_star_time:?}"
+                );
+            }
+        }
     }
 }
@@ -1,8 +1,6 @@
-use ahash::AHashSet;
 use eyre::{eyre, Error};
 use itertools::Itertools;
-use num::Integer;
-use std::cmp::{max, min};
+use std::fmt::Write;
 use std::str::FromStr;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -25,183 +23,244 @@ impl FromStr for Direction {
         }
     }
 }
-#[derive(Debug)]
-struct Trench<const COLOR_FIRST: bool> {
-    dug_edges: Vec<((usize, usize), (usize, usize))>,
+/// how the instructions are read
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Interpretation {
+    /// direction and length as written
+    Plain,
+    /// the length is the first 5 hexadecimal digits of the colour, the last one being the direction
+    HexEncoded,
 }
 
-impl<const COLOR_FIRST: bool> FromStr for Trench<COLOR_FIRST> {
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Instruction {
+    dir: Direction,
+    len: usize,
+    /// `#rrggbb`
+    color: String,
+}
+
+impl FromStr for Instruction {
     type Err = Error;
 
+    /// `R 6 (#70c710)`
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let dug: Result<Vec<(Direction, usize)>, Self::Err> = s
-            .lines()
-            .filter(|l| !l.is_empty())
-            .map(|l| {
-                let mut parts = l.split(' ');
-                if !COLOR_FIRST {
-                    let dir = parts
-                        .next()
-                        .ok_or_else(|| eyre!("No direction provided in {l}"))
-                        .and_then(str::parse::<Direction>)
-                        .map_err(|e| eyre!("parse error {e} on {l}"))?;
-                    let len: usize = parts
-                        .next()
-                        .ok_or_else(|| eyre!("No len provided in {l}"))
-                        .and_then(|len| len.parse().map_err(|e| eyre!("len read error {e}")))?;
-                    //let color :&str = parts.next().ok_or_else(||eyre!("No color provided in {l}"))?;
-                    Ok((dir, len))
-                } else {
-                    let info = parts.nth(2).unwrap();
-                    let l = info.len();
-                    let info = &info[2..l - 1];
-                    let len = info[0..5]
-                        .chars()
-                        .map(|c| c.to_digit(16).unwrap())
-                        .reduce(|acc, m| acc * 16 + m)
-                        .unwrap() as usize;
-                    let dir = match info.chars().nth(5).unwrap() {
-                        '0' => Direction::Right,
-                        '1' => Direction::Down,
-                        '2' => Direction::Left,
-                        '3' => Direction::Up,
-                        _other => panic!("unexpected direction {_other}"),
-                    };
-                    Ok((dir, len))
-                }
-            })
-            .collect();
-        let dug = dug?;
-
-        let points = dug.into_iter().fold(vec![(0isize, 0isize)], |mut acc, (dir, len)| {
-            let (x, y) = acc.last().copied().unwrap();
-            match dir {
-                Direction::Up => {
-                    acc.push((x, y - len as isize));
-                }
-                Direction::Left => {
-                    acc.push((x - len as isize, y));
-                }
-                Direction::Down => {
-                    acc.push((x, y + len as isize));
-                }
-                Direction::Right => {
-                    acc.push((x + len as isize, y));
-                }
-            }
-            acc
-        });
-
-        let minx = points
-            .iter()
-            .map(|(x, _)| x)
-            .min()
-            .copied()
-            .ok_or_else(|| eyre!("no points in Trench"))?;
-        let miny = points
-            .iter()
-            .map(|(_, y)| y)
-            .min()
-            .copied()
-            .ok_or_else(|| eyre!("no points in Trench"))?;
-        println!("minx {minx}, miny  {miny}");
-        let dug_edges: Vec<_> = points
-            .into_iter()
-            .tuple_windows::<(_, _)>()
-            .map(|((x1, y1), (x2, y2))| {
-                (
-                    ((x1 - minx + 1) as usize, (y1 - miny + 1) as usize),
-                    ((x2 - minx + 1) as usize, (y2 - miny + 1) as usize),
-                )
-            })
-            .collect();
+        let mut parts = s.split(' ');
+        let dir = parts
+            .next()
+            .ok_or_else(|| eyre!("No direction provided in {s}"))
+            .and_then(str::parse::<Direction>)
+            .map_err(|e| eyre!("parse error {e} on {s}"))?;
+        let len: usize = parts
+            .next()
+            .ok_or_else(|| eyre!("No len provided in {s}"))
+            .and_then(|len| len.parse().map_err(|e| eyre!("len read error {e}")))?;
+        let color = parts
+            .next()
+            .and_then(|c| c.strip_prefix('(')?.strip_suffix(')'))
+            .ok_or_else(|| eyre!("No color provided in {s}"))?;
+        if color.len() != 7
+            || !color.starts_with('#')
+            || !color[1..].chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return Err(eyre!("color {color} is not #rrggbb in {s}"));
+        }
         Ok(Self {
-            dug_edges,
-            // maxx,
-            // maxy,
+            dir,
+            len,
+            color: color.to_string(),
         })
     }
 }
 
-impl<const COLOR_FIRST: bool> Trench<COLOR_FIRST> {
-    fn compute_area(&self) -> usize {
-        let lines_y = self
-            .dug_edges
-            .iter()
-            .filter_map(|((_, y1), (_, y2))| (*y1 == *y2).then_some(*y1))
-            .sorted()
-            .unique()
-            .collect_vec();
-        let column_x = self
-            .dug_edges
-            .iter()
-            .filter_map(|((x1, _), (x2, _))| (*x1 == *x2).then_some(*x1))
-            .sorted()
-            .unique()
-            .collect_vec();
-
-        let mut area = 0;
-        let mut rectangles_in: AHashSet<(usize, usize)> = AHashSet::new();
-        for (j, (y1, y2)) in lines_y.iter().copied().tuple_windows().enumerate() {
-            for (i, (x1, x2)) in column_x.iter().copied().tuple_windows().enumerate() {
-                let column_before = self
-                    .dug_edges
-                    .iter()
-                    .filter_map(|((x1, y1), (x2, y2))| {
-                        if *x1 == *x2 {
-                            Some((*x1, min(*y1, *y2), max(*y1, *y2)))
-                        } else {
-                            None
-                        }
-                    })
-                    .filter(|(x, ymin, ymax)| *x <= x1 && *ymin <= y1 && *ymax >= y2)
-                    .count();
-                if column_before.is_odd() {
-                    rectangles_in.insert((i, j));
-                    area += (x2 + 1 - x1) * (y2 + 1 - y1);
-                    let left_inside = i > 0 && rectangles_in.contains(&(i - 1, j));
-                    if left_inside {
-                        //rectangle on the left is also in the area, left side as been counted twice
-                        area -= y2 + 1 - y1;
-                    }
-                    let up_inside = j > 0 && rectangles_in.contains(&(i, j - 1));
-                    if up_inside {
-                        //rectangle above is also in the area, left side as been counted twice
-                        area -= x2 + 1 - x1;
-                    }
-                    if left_inside && up_inside {
-                        // edge has been removed twice
-                        area += 1;
-                    }
-                    if j > 0 {
-                        let up_right_inside = rectangles_in.contains(&(i + 1, j - 1));
-                        if up_right_inside && !up_inside {
-                            area -= 1;
-                        }
-                    }
-                    if i > 0 && j > 0 {
-                        let upleft_inside = rectangles_in.contains(&(i - 1, j - 1));
-                        if upleft_inside && !(up_inside || left_inside) {
-                            area -= 1;
-                        }
-                    }
-                }
+impl Instruction {
+    fn interpret(&self, interpretation: Interpretation) -> Result<(Direction, usize), Error> {
+        match interpretation {
+            Interpretation::Plain => Ok((self.dir, self.len)),
+            Interpretation::HexEncoded => {
+                let len = usize::from_str_radix(&self.color[1..6], 16)?;
+                let dir = match &self.color[6..] {
+                    "0" => Direction::Right,
+                    "1" => Direction::Down,
+                    "2" => Direction::Left,
+                    "3" => Direction::Up,
+                    other => return Err(eyre!("unexpected direction {other} in {}", self.color)),
+                };
+                Ok((dir, len))
             }
         }
-        area
     }
 }
 
-pub fn dig_lagoon() {
-    let trench: Trench<false> =
-        include_str!("../resources/day18_dig_instructions.txt").parse().unwrap();
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Segment {
+    start: (i64, i64),
+    end: (i64, i64),
+    /// colour of the instruction digging it
+    color: String,
+}
+
+impl Segment {
+    /// (min, max) corners of the segment
+    fn bounds(&self) -> ((i64, i64), (i64, i64)) {
+        let (x1, y1) = self.start;
+        let (x2, y2) = self.end;
+        ((x1.min(x2), y1.min(y2)), (x1.max(x2), y1.max(y2)))
+    }
+
+    /// common points of two axis aligned segments, as (min, max) corners
+    fn overlap(&self, other: &Segment) -> Option<((i64, i64), (i64, i64))> {
+        let ((ax1, ay1), (ax2, ay2)) = self.bounds();
+        let ((bx1, by1), (bx2, by2)) = other.bounds();
+        let (x1, y1, x2, y2) = (ax1.max(bx1), ay1.max(by1), ax2.min(bx2), ay2.min(by2));
+        (x1 <= x2 && y1 <= y2).then_some(((x1, y1), (x2, y2)))
+    }
+}
+
+/// segments dug from the origin, in instruction order
+#[derive(Debug)]
+struct Trench {
+    segments: Vec<Segment>,
+}
+
+impl Trench {
+    /// the trench must come back to the origin without crossing or touching itself
+    fn new(instructions: &[Instruction], interpretation: Interpretation) -> Result<Self, Error> {
+        let mut segments = vec![];
+        let mut start = (0i64, 0i64);
+        for instruction in instructions {
+            let (dir, len) = instruction.interpret(interpretation)?;
+            if len == 0 {
+                return Err(eyre!("empty segment from {start:?}"));
+            }
+            let len = len as i64;
+            let (x, y) = start;
+            let end = match dir {
+                Direction::Up => (x, y - len),
+                Direction::Left => (x - len, y),
+                Direction::Down => (x, y + len),
+                Direction::Right => (x + len, y),
+            };
+            segments.push(Segment {
+                start,
+                end,
+                color: instruction.color.clone(),
+            });
+            start = end;
+        }
+        if segments.is_empty() {
+            return Err(eyre!("no segments in Trench"));
+        }
+        if start != (0, 0) {
+            return Err(eyre!("trench is not closed, it ends on {start:?}"));
+        }
+
+        let n = segments.len();
+        for (i, j) in (0..n).tuple_combinations() {
+            let Some(overlap) = segments[i].overlap(&segments[j]) else {
+                continue;
+            };
+            // consecutive segments only share their common end
+            let common = if j == i + 1 {
+                Some(segments[i].end)
+            } else if i == 0 && j == n - 1 {
+                Some(segments[i].start)
+            } else {
+                None
+            };
+            if common.map(|c| (c, c)) != Some(overlap) {
+                return Err(eyre!(
+                    "trench crosses itself on {overlap:?}, segments {i} and {j}"
+                ));
+            }
+        }
+
+        Ok(Self { segments })
+    }
+
+    fn parse(input: &str, interpretation: Interpretation) -> Result<Self, Error> {
+        let instructions: Vec<Instruction> =
+            input.lines().filter(|l| !l.is_empty()).map(str::parse).collect::<Result<_, _>>()?;
+        Self::new(&instructions, interpretation)
+    }
+
+    /// shoelace formula gives the area of the polygon linking the dug blocks centers,
+    /// then Pick's theorem (A = i + b/2 - 1) gives the inner blocks count, b being the dug ones
+    fn compute_area(&self) -> u64 {
+        let double_area: i64 =
+            self.segments.iter().map(|s| s.start.0 * s.end.1 - s.end.0 * s.start.1).sum();
+        let boundary: u64 = self
+            .segments
+            .iter()
+            .map(|s| s.start.0.abs_diff(s.end.0) + s.start.1.abs_diff(s.end.1))
+            .sum();
+        (double_area.unsigned_abs() + boundary) / 2 + 1
+    }
+
+    /// the lagoon fitting a `size` pixels square : its interior is filled,
+    /// and each segment stroked in its colour, one block wide but at least one pixel
+    fn to_svg(&self, size: f64) -> String {
+        let ((minx, miny), (maxx, maxy)) = self.segments.iter().map(Segment::bounds).fold(
+            ((i64::MAX, i64::MAX), (i64::MIN, i64::MIN)),
+            |((x1, y1), (x2, y2)), ((sx1, sy1), (sx2, sy2))| {
+                ((x1.min(sx1), y1.min(sy1)), (x2.max(sx2), y2.max(sy2)))
+            },
+        );
+        // one more block, as the dug blocks centers are drawn
+        let (width, height) = ((maxx - minx + 1) as f64, (maxy - miny + 1) as f64);
+        let scale = size / width.max(height);
+        let point = |(x, y): (i64, i64)| {
+            format!(
+                "{:.1},{:.1}",
+                ((x - minx) as f64 + 0.5) * scale,
+                ((y - miny) as f64 + 0.5) * scale
+            )
+        };
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\">\n",
+            (width * scale).round(),
+            (height * scale).round()
+        );
+        let _ = writeln!(
+            svg,
+            "<polygon points=\"{}\" fill=\"lightblue\"/>",
+            self.segments.iter().map(|s| point(s.start)).join(" ")
+        );
+        let stroke = scale.max(1.);
+        for segment in &self.segments {
+            let _ = writeln!(
+                svg,
+                "<polyline points=\"{} {}\" stroke=\"{}\" stroke-width=\"{stroke:.1}\" stroke-linecap=\"square\" fill=\"none\"/>",
+                point(segment.start),
+                point(segment.end),
+                segment.color
+            );
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+pub fn dig_lagoon(export: bool) {
+    let input = include_str!("../resources/day18_dig_instructions.txt");
+    let trench = Trench::parse(input, Interpretation::Plain).unwrap();
     let area = trench.compute_area();
     println!("area: {area}");
+    let colors = trench.segments.iter().map(|s| &s.color).unique().count();
+    println!("{} segments, {colors} colors", trench.segments.len());
 
-    let trench: Trench<true> =
-        include_str!("../resources/day18_dig_instructions.txt").parse().unwrap();
+    if export {
+        std::fs::write(".day18_lagoon.svg", trench.to_svg(1000.)).unwrap();
+    }
+
+    let trench = Trench::parse(input, Interpretation::HexEncoded).unwrap();
     let area = trench.compute_area();
     println!("area: {area}");
+    if export {
+        std::fs::write(".day18_lagoon_hex.svg", trench.to_svg(1000.)).unwrap();
+        println!("lagoons drawn to .day18_lagoon.svg and .day18_lagoon_hex.svg");
+    }
 }
 
 #[cfg(test)]
@@ -226,10 +285,113 @@ mod tests {
             L 2 (#015232)
             U 2 (#7a21e3)
         "};
-        let trench: Trench<false> = input.parse().unwrap();
+        let trench = Trench::parse(input, Interpretation::Plain).unwrap();
         assert_eq!(62, trench.compute_area());
+        assert_eq!("#70c710", trench.segments[0].color);
+        assert_eq!(
+            ((6, 0), (6, 5)),
+            (trench.segments[1].start, trench.segments[1].end)
+        );
 
-        let trench: Trench<true> = input.parse().unwrap();
+        let trench = Trench::parse(input, Interpretation::HexEncoded).unwrap();
         assert_eq!(952408144115, trench.compute_area());
+        assert_eq!("#70c710", trench.segments[0].color);
+        assert_eq!((461937, 0), trench.segments[0].end);
+    }
+
+    #[test]
+    fn validation_works() {
+        let parse = |input| Trench::parse(input, Interpretation::Plain);
+        assert!(parse("R 2 (#000000)\nD 2 (#000000)\n").is_err());
+        assert!(parse("R 2 (#000000)\nL 2 (#000000)\n").is_err());
+        assert!(parse("R 2 (#000000)\nD 0 (#000000)\nL 2 (#000000)\n").is_err());
+        assert!(parse("R 2 (#00000)\nL 2 (#000000)\n").is_err());
+        assert!(parse("R 2 #000000\nL 2 (#000000)\n").is_err());
+        assert!(parse("").is_err());
+        // crossing itself on (1, 0)
+        assert!(parse(indoc! {"
+            R 2 (#000000)
+            D 1 (#000000)
+            L 1 (#000000)
+            U 2 (#000000)
+            L 1 (#000000)
+            D 1 (#000000)
+        "})
+        .is_err());
+        // touching itself on (1, 0)
+        assert!(parse(indoc! {"
+            R 2 (#000000)
+            D 2 (#000000)
+            L 1 (#000000)
+            U 2 (#000000)
+            L 1 (#000000)
+            D 1 (#000000)
+            U 1 (#000000)
+        "})
+        .is_err());
+
+        // a 3x3 square, then a 5x4 rectangle with a 1x2 notch
+        assert_eq!(
+            9,
+            parse("R 2 (#000000)\nD 2 (#000000)\nL 2 (#000000)\nU 2 (#000000)")
+                .unwrap()
+                .compute_area()
+        );
+        let u_shape = indoc! {"
+            R 1 (#000000)
+            D 2 (#000000)
+            R 2 (#000000)
+            U 2 (#000000)
+            R 1 (#000000)
+            D 3 (#000000)
+            L 4 (#000000)
+            U 3 (#000000)
+        "};
+        assert_eq!(18, parse(u_shape).unwrap().compute_area());
+    }
+
+    #[test]
+    fn svg_export_works() {
+        let input = indoc! {"
+            R 2 (#ff0000)
+            D 1 (#00ff00)
+            L 2 (#0000ff)
+            U 1 (#123abc)
+        "};
+        // 3x2 blocks, 10 pixels each
+        let svg = Trench::parse(input, Interpretation::Plain).unwrap().to_svg(30.);
+        assert!(svg.starts_with(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"30\" height=\"20\">\n"
+        ));
+        assert!(svg.contains(
+            "<polygon points=\"5.0,5.0 25.0,5.0 25.0,15.0 5.0,15.0\" fill=\"lightblue\"/>\n"
+        ));
+        assert!(svg.contains(
+            "<polyline points=\"25.0,5.0 25.0,15.0\" stroke=\"#00ff00\" stroke-width=\"10.0\""
+        ));
+        assert_eq!(4, svg.matches("<polyline").count());
+        assert!(svg.ends_with("</svg>\n"));
+
+        // huge coordinates still fit, with visible strokes
+        let input = indoc! {"
+            R 6 (#70c710)
+            D 5 (#0dc571)
+            L 2 (#5713f0)
+            D 2 (#d2c081)
+            R 2 (#59c680)
+            D 2 (#411b91)
+            L 5 (#8ceee2)
+            U 2 (#caa173)
+            L 1 (#1b58a2)
+            U 2 (#caa171)
+            R 2 (#7807d2)
+            U 3 (#a77fa3)
+            L 2 (#015232)
+            U 2 (#7a21e3)
+        "};
+        let trench = Trench::parse(input, Interpretation::HexEncoded).unwrap();
+        let svg = trench.to_svg(1000.);
+        assert!(svg.contains("width=\"1000\""));
+        assert!(svg.contains("stroke=\"#70c710\" stroke-width=\"1.0\""));
     }
 }
@@ -1,6 +1,8 @@
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
+use eyre::{eyre, Error};
 use itertools::Itertools;
 use std::cmp::{max, min};
+use std::fmt::{Display, Formatter};
 use std::iter::once;
 use std::str::FromStr;
 
@@ -35,11 +37,31 @@ impl InstrFilter {
         }
     }
 }
-#[derive(Debug, Clone)]
+impl Display for InstrFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Greater(c, v) => write!(f, "{c}>{v}"),
+            Self::Lesser(c, v) => write!(f, "{c}<{v}"),
+            Self::All => f.write_str("always"),
+            Self::Reject => f.write_str("never"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct Instruction {
     filter: InstrFilter,
     out: String,
 }
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.filter {
+            InstrFilter::All => f.write_str(&self.out),
+            filter => write!(f, "{filter}:{}", self.out),
+        }
+    }
+}
 impl Instruction {
     fn accept(&self, part: &Part) -> Option<String> {
         match self.filter {
@@ -63,9 +85,52 @@ impl Instruction {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct Filter {
     workflows: AHashMap<String, Vec<Instruction>>,
 }
+
+impl Display for Filter {
+    /// same format as the input, `in` first then by name
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for name in self.workflows.keys().sorted_by_key(|n| (*n != "in", *n)) {
+            writeln!(f, "{name}{{{}}}", self.workflows[name].iter().join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// the instruction firing in a workflow, `rule` being its index
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step<'a> {
+    workflow: &'a str,
+    rule: usize,
+    instruction: &'a Instruction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Trace<'a> {
+    steps: Vec<Step<'a>>,
+    accepted: bool,
+}
+
+impl Display for Trace<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for Step {
+            workflow,
+            rule,
+            instruction,
+        } in &self.steps
+        {
+            writeln!(f, "{workflow} : rule {} '{instruction}'", rule + 1)?;
+        }
+        f.write_str(if self.accepted {
+            "accepted"
+        } else {
+            "rejected"
+        })
+    }
+}
 impl FromStr for Filter {
     type Err = ();
 
@@ -108,21 +173,43 @@ impl FromStr for Filter {
     }
 }
 impl Filter {
-    fn apply(instructions: &[Instruction], part: &Part) -> String {
-        for i in instructions {
-            if let Some(out) = i.accept(part) {
-                return out;
+    /// workflows followed by the part from `in`, with the instruction that fired in each of them
+    fn trace(&self, part: &Part) -> Result<Trace<'_>, Error> {
+        let mut steps: Vec<Step> = vec![];
+        let mut pos = "in";
+        while pos != "A" && pos != "R" {
+            let (workflow, instructions) = self
+                .workflows
+                .get_key_value(pos)
+                .ok_or_else(|| eyre!("undefined workflow {pos}"))?;
+            if steps.iter().any(|s| s.workflow == workflow) {
+                return Err(eyre!(
+                    "workflow {workflow} is reached twice, the part loops"
+                ));
             }
+            let (rule, instruction) = instructions
+                .iter()
+                .enumerate()
+                .find(|(_, i)| i.accept(part).is_some())
+                .ok_or_else(|| eyre!("no rule of workflow {workflow} fired"))?;
+            pos = match instruction.filter {
+                InstrFilter::Reject => "R",
+                _ => &instruction.out,
+            };
+            steps.push(Step {
+                workflow,
+                rule,
+                instruction,
+            });
         }
-        unreachable!()
+        Ok(Trace {
+            steps,
+            accepted: pos == "A",
+        })
     }
+
     fn accept(&self, part: &Part) -> bool {
-        let mut pos = "in".to_string();
-        while pos != "A" && pos != "R" {
-            let instr = self.workflows.get(&pos).unwrap();
-            pos = Self::apply(instr, part);
-        }
-        &pos == "A"
+        self.trace(part).unwrap().accepted
     }
 
     fn compact(filters: &[InstrFilter]) -> Vec<InstrFilter> {
@@ -236,7 +323,229 @@ impl Filter {
     }
 }
 
-pub fn filter_parts() {
+/// inclusive ranges of the x, m, a and s ratings
+type Ranges = [(usize, usize); 4];
+
+const ALL_RATINGS: Ranges = [(1, 4000); 4];
+
+impl InstrFilter {
+    /// (ratings matching the filter, ratings not matching it), None when empty
+    fn split(&self, ranges: Ranges) -> (Option<Ranges>, Option<Ranges>) {
+        let restrict = |c: char, lo: usize, hi: usize| {
+            let mut ranges = ranges;
+            let range = &mut ranges["xmas".find(c).unwrap()];
+            *range = (range.0.max(lo), range.1.min(hi));
+            (range.0 <= range.1).then_some(ranges)
+        };
+        match *self {
+            Self::Greater(c, v) => (restrict(c, v + 1, usize::MAX), restrict(c, 0, v)),
+            Self::Lesser(_, 0) => (None, Some(ranges)),
+            Self::Lesser(c, v) => (restrict(c, 0, v - 1), restrict(c, v, usize::MAX)),
+            Self::All | Self::Reject => (Some(ranges), None),
+        }
+    }
+}
+
+/// findings of the static analysis, everything sorted by name
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Analysis {
+    /// workflows never reached from `in`
+    unreachable: Vec<String>,
+    /// (workflow, rule index) of the rules no part can fire, given the previous rules of the workflow
+    dead_rules: Vec<(String, usize)>,
+    /// groups of workflows leading back to each other
+    cycles: Vec<Vec<String>>,
+    /// (workflow, target) of targets which are neither a workflow, A nor R
+    undefined: Vec<(String, String)>,
+}
+
+impl Display for Analysis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "unreachable workflows : {}", self.unreachable.join(", "))?;
+        writeln!(
+            f,
+            "rules never firing : {}",
+            self.dead_rules.iter().map(|(w, r)| format!("{w} rule {}", r + 1)).join(", ")
+        )?;
+        writeln!(
+            f,
+            "cycles : {}",
+            self.cycles.iter().map(|c| format!("[{}]", c.join(", "))).join(", ")
+        )?;
+        write!(
+            f,
+            "undefined workflows : {}",
+            self.undefined.iter().map(|(w, t)| format!("{t} in {w}")).join(", ")
+        )
+    }
+}
+
+impl Filter {
+    fn targets<'a>(&'a self, workflow: &str) -> impl Iterator<Item = &'a str> {
+        self.workflows.get(workflow).into_iter().flatten().map(|i| i.out.as_str())
+    }
+
+    /// workflows reached from the given one, itself included only through a cycle
+    fn reached_from(&self, workflow: &str) -> AHashSet<&str> {
+        let mut reached = AHashSet::new();
+        let mut to_visit = self.targets(workflow).collect_vec();
+        while let Some(w) = to_visit.pop() {
+            if self.workflows.contains_key(w) && reached.insert(w) {
+                to_visit.extend(self.targets(w));
+            }
+        }
+        reached
+    }
+
+    /// (rule index, ratings firing it) for each rule which may fire
+    fn live_rules(instructions: &[Instruction]) -> (Vec<(usize, Ranges)>, Option<Ranges>) {
+        let mut live = vec![];
+        let mut remaining = Some(ALL_RATINGS);
+        for (rule, instruction) in instructions.iter().enumerate() {
+            let Some(ranges) = remaining else {
+                break;
+            };
+            let (fired, rest) = instruction.filter.split(ranges);
+            if let Some(fired) = fired {
+                live.push((rule, fired));
+            }
+            remaining = rest;
+        }
+        (live, remaining)
+    }
+
+    fn analyse(&self) -> Analysis {
+        let mut analysis = Analysis::default();
+        let reached = self.reached_from("in");
+        analysis.unreachable = self
+            .workflows
+            .keys()
+            .filter(|w| *w != "in" && !reached.contains(w.as_str()))
+            .cloned()
+            .sorted()
+            .collect();
+
+        for (name, instructions) in self.workflows.iter().sorted_by_key(|(n, _)| *n) {
+            let (live, _) = Self::live_rules(instructions);
+            let mut live = live.into_iter().map(|(rule, _)| rule).peekable();
+            for rule in 0..instructions.len() {
+                if live.next_if_eq(&rule).is_none() {
+                    analysis.dead_rules.push((name.clone(), rule));
+                }
+            }
+            for target in instructions.iter().map(|i| &i.out).unique().sorted() {
+                if target != "A" && target != "R" && !self.workflows.contains_key(target) {
+                    analysis.undefined.push((name.clone(), target.clone()));
+                }
+            }
+        }
+
+        let reached_from: AHashMap<&str, AHashSet<&str>> =
+            self.workflows.keys().map(|w| (w.as_str(), self.reached_from(w))).collect();
+        analysis.cycles = reached_from
+            .iter()
+            .filter(|(w, reached)| reached.contains(*w))
+            .map(|(w, reached)| {
+                reached
+                    .iter()
+                    .filter(|r| reached_from[*r].contains(w))
+                    .map(|r| r.to_string())
+                    .sorted()
+                    .collect_vec()
+            })
+            .unique()
+            .sorted()
+            .collect();
+        analysis
+    }
+
+    /// an equivalent filter : rules never firing are dropped, rules ending a workflow with the same
+    /// target as its last rule are collapsed into it, workflows with a single rule are replaced by
+    /// its target, a workflow only reached by the last rule of another one is merged into it,
+    /// and unreachable workflows are dropped
+    fn optimise(&self) -> Filter {
+        let mut filter = self.clone();
+        loop {
+            let before = filter.clone();
+
+            for instructions in filter.workflows.values_mut() {
+                let (live, remaining) = Self::live_rules(instructions);
+                let mut kept =
+                    live.iter().map(|(rule, _)| instructions[*rule].clone()).collect_vec();
+                // nothing goes past the last rule : it always fires
+                if let (Some(last), None) = (kept.last_mut(), remaining) {
+                    last.filter = InstrFilter::All;
+                }
+                while kept.len() > 1
+                    && kept[kept.len() - 1].filter == InstrFilter::All
+                    && kept[kept.len() - 2].out == kept[kept.len() - 1].out
+                {
+                    kept.remove(kept.len() - 2);
+                }
+                *instructions = kept;
+            }
+
+            let aliases: AHashMap<String, String> = filter
+                .workflows
+                .iter()
+                .filter(|(name, _)| *name != "in")
+                .filter_map(|(name, instructions)| match &instructions[..] {
+                    [Instruction {
+                        filter: InstrFilter::All,
+                        out,
+                    }] if out != name => Some((name.clone(), out.clone())),
+                    _ => None,
+                })
+                .collect();
+            for instruction in filter.workflows.values_mut().flatten() {
+                // aliases of aliases are resolved by the next pass
+                if let Some(target) = aliases.get(&instruction.out) {
+                    instruction.out = target.clone();
+                }
+            }
+
+            let references = filter.workflows.values().flatten().map(|i| i.out.as_str()).counts();
+            let merge = filter.workflows.iter().sorted_by_key(|(n, _)| *n).find_map(
+                |(name, instructions)| {
+                    let last = instructions.last()?;
+                    (last.filter == InstrFilter::All
+                        && last.out != *name
+                        && last.out != "in"
+                        && filter.workflows.contains_key(&last.out)
+                        && references[last.out.as_str()] == 1)
+                        .then(|| (name.clone(), last.out.clone()))
+                },
+            );
+            if let Some((name, merged)) = merge {
+                let merged = filter.workflows.remove(&merged).unwrap();
+                let instructions = filter.workflows.get_mut(&name).unwrap();
+                instructions.pop();
+                instructions.extend(merged);
+            }
+
+            let reached = filter.reached_from("in");
+            let unreachable = filter
+                .workflows
+                .keys()
+                .filter(|w| *w != "in" && !reached.contains(w.as_str()))
+                .cloned()
+                .collect_vec();
+            for w in unreachable {
+                filter.workflows.remove(&w);
+            }
+
+            if filter == before {
+                return filter;
+            }
+        }
+    }
+
+    fn rules_count(&self) -> usize {
+        self.workflows.values().map(Vec::len).sum()
+    }
+}
+
+pub fn filter_parts(export: bool) {
     let input = include_str!("../resources/day19_workflows_parts.txt");
     // let (filter,parts)=read_input(input);
     let sum_filtered = sum_accepted_parts(input);
@@ -245,7 +554,41 @@ pub fn filter_parts() {
     let (filter, _) = read_input(input);
     let combinations = filter.accepted_combinations();
     println!("combinations : {combinations}");
+
+    println!("{}", filter.analyse());
+    let optimised = filter.optimise();
+    println!(
+        "optimised from {} workflows and {} rules to {} workflows and {} rules",
+        filter.workflows.len(),
+        filter.rules_count(),
+        optimised.workflows.len(),
+        optimised.rules_count()
+    );
+    println!(
+        "optimised combinations : {}",
+        optimised.accepted_combinations()
+    );
+    if export {
+        std::fs::write(".day19_optimised_workflows.txt", optimised.to_string()).unwrap();
+        println!("optimised workflows written to .day19_optimised_workflows.txt");
+    }
 }
+/// explains the way of each given part through the puzzle workflows
+pub fn explain_parts(parts: &[String]) {
+    if parts.is_empty() {
+        println!("usage : explain-parts '{{x=787,m=2655,a=1222,s=2876}}' ...");
+        return;
+    }
+    let (filter, _) = read_input(include_str!("../resources/day19_workflows_parts.txt"));
+    for part in parts {
+        let trace = part.parse::<Part>().and_then(|p| filter.trace(&p));
+        match trace {
+            Ok(trace) => println!("{part}\n{trace}\n"),
+            Err(e) => println!("{part}\nerror : {e}\n"),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Part {
     x: usize,
@@ -254,15 +597,34 @@ struct Part {
     s: usize,
 }
 impl FromStr for Part {
-    type Err = ();
+    type Err = Error;
 
+    /// `{x=787,m=2655,a=1222,s=2876}`
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.split(&['{', '}', ',']).filter(|p| !p.is_empty());
-        let x: usize = parts.next().unwrap().split_once('=').unwrap().1.parse().unwrap();
-        let m: usize = parts.next().unwrap().split_once('=').unwrap().1.parse().unwrap();
-        let a: usize = parts.next().unwrap().split_once('=').unwrap().1.parse().unwrap();
-        let s: usize = parts.next().unwrap().split_once('=').unwrap().1.parse().unwrap();
-        Ok(Self { x, m, a, s })
+        let ratings: AHashMap<&str, usize> = s
+            .trim()
+            .strip_prefix('{')
+            .and_then(|s| s.strip_suffix('}'))
+            .ok_or_else(|| eyre!("part {s} is not within braces"))?
+            .split(',')
+            .map(|r| {
+                let (carac, value) =
+                    r.split_once('=').ok_or_else(|| eyre!("no '=' in rating {r} of {s}"))?;
+                let value = value.parse().map_err(|e| eyre!("rating {r} of {s} : {e}"))?;
+                Ok((carac, value))
+            })
+            .collect::<Result<_, Error>>()?;
+        let rating =
+            |carac| ratings.get(carac).copied().ok_or_else(|| eyre!("no rating {carac} in {s}"));
+        if ratings.len() != 4 {
+            return Err(eyre!("unexpected ratings in {s}"));
+        }
+        Ok(Self {
+            x: rating("x")?,
+            m: rating("m")?,
+            a: rating("a")?,
+            s: rating("s")?,
+        })
     }
 }
 impl Part {
@@ -326,4 +688,161 @@ mod tests {
         let (filter, _) = read_input(input);
         assert_eq!(167409079868000, filter.accepted_combinations());
     }
+
+    #[test]
+    fn trace_works() {
+        let (filter, parts) = read_input(indoc! {"
+            px{a<2006:qkq,m>2090:A,rfg}
+            pv{a>1716:R,A}
+            lnx{m>1548:A,A}
+            rfg{s<537:gd,x>2440:R,A}
+            qs{s>3448:A,lnx}
+            qkq{x<1416:A,crn}
+            crn{x>2662:A,R}
+            in{s<1351:px,qqz}
+            qqz{s>2770:qs,m<1801:hdj,R}
+            gd{a>3333:R,R}
+            hdj{m>838:A,pv}
+
+            {x=787,m=2655,a=1222,s=2876}
+            {x=1679,m=44,a=2067,s=496}
+        "});
+        let trace = filter.trace(&parts[0]).unwrap();
+        assert!(trace.accepted);
+        assert_eq!(
+            vec![("in", 1), ("qqz", 0), ("qs", 1), ("lnx", 0)],
+            trace.steps.iter().map(|s| (s.workflow, s.rule)).collect_vec()
+        );
+        assert_eq!(
+            "in : rule 2 'qqz'\nqqz : rule 1 's>2770:qs'\nqs : rule 2 'lnx'\nlnx : rule 1 'm>1548:A'\naccepted",
+            trace.to_string()
+        );
+        // in -> px -> rfg -> gd -> R
+        let trace = filter.trace(&parts[1]).unwrap();
+        assert!(!trace.accepted);
+        assert_eq!(4, trace.steps.len());
+        assert_eq!(
+            (1, "R"),
+            (
+                trace.steps[3].rule,
+                trace.steps[3].instruction.to_string().as_str()
+            )
+        );
+
+        let (looping, _) = read_input("in{x>10:loop,A}\nloop{x>10:in,A}\n\n");
+        assert!(looping.trace(&"{x=11,m=0,a=0,s=0}".parse().unwrap()).is_err());
+        assert!(looping.trace(&"{x=1,m=0,a=0,s=0}".parse().unwrap()).unwrap().accepted);
+        let (undefined, _) = read_input("in{x>10:nowhere,A}\n\n");
+        assert!(undefined.trace(&"{x=11,m=0,a=0,s=0}".parse().unwrap()).is_err());
+
+        assert!("{x=1,m=2,a=3}".parse::<Part>().is_err());
+        assert!("{x=1,m=2,a=3,s=b}".parse::<Part>().is_err());
+        assert!("x=1,m=2,a=3,s=4".parse::<Part>().is_err());
+        assert!("{x=1,m=2,a=3,z=4}".parse::<Part>().is_err());
+    }
+
+    #[test]
+    fn analysis_works() {
+        let (filter, _) = read_input(indoc! {"
+            in{x>10:a,x>20:R,m<1:R,b}
+            a{s<100:b,A}
+            b{m>5:a,undefined}
+            c{A}
+
+        "});
+        assert_eq!(
+            Analysis {
+                unreachable: vec!["c".to_string()],
+                dead_rules: vec![("in".to_string(), 1), ("in".to_string(), 2)],
+                cycles: vec![vec!["a".to_string(), "b".to_string()]],
+                undefined: vec![("b".to_string(), "undefined".to_string())],
+            },
+            filter.analyse()
+        );
+        assert_eq!(
+            indoc! {"
+                unreachable workflows : c
+                rules never firing : in rule 2, in rule 3
+                cycles : [a, b]
+                undefined workflows : undefined in b"
+            },
+            filter.analyse().to_string()
+        );
+        let (self_loop, _) = read_input("in{x>10:in,A}\n\n");
+        assert_eq!(vec![vec!["in".to_string()]], self_loop.analyse().cycles);
+    }
+
+    #[test]
+    fn optimise_works() {
+        let input = indoc! {"
+            px{a<2006:qkq,m>2090:A,rfg}
+            pv{a>1716:R,A}
+            lnx{m>1548:A,A}
+            rfg{s<537:gd,x>2440:R,A}
+            qs{s>3448:A,lnx}
+            qkq{x<1416:A,crn}
+            crn{x>2662:A,R}
+            in{s<1351:px,qqz}
+            qqz{s>2770:qs,m<1801:hdj,R}
+            gd{a>3333:R,R}
+            hdj{m>838:A,pv}
+
+            {x=787,m=2655,a=1222,s=2876}
+            {x=1679,m=44,a=2067,s=496}
+            {x=2036,m=264,a=79,s=2244}
+            {x=2461,m=1339,a=466,s=291}
+            {x=2127,m=1623,a=2188,s=1013}
+        "};
+        let (filter, parts) = read_input(input);
+        let optimised = filter.optimise();
+        // gd, lnx then qs always lead to R or A, qqz is merged into in, pv into hdj,
+        // crn into qkq and rfg into px
+        assert_eq!(
+            indoc! {"
+                in{s<1351:px,s>2770:A,m<1801:hdj,R}
+                hdj{m>838:A,a>1716:R,A}
+                px{a<2006:qkq,m>2090:A,s<537:R,x>2440:R,A}
+                qkq{x<1416:A,x>2662:A,R}
+            "},
+            optimised.to_string()
+        );
+        assert_eq!(
+            filter.accepted_combinations(),
+            optimised.accepted_combinations()
+        );
+        for p in &parts {
+            assert_eq!(filter.accept(p), optimised.accept(p));
+        }
+        assert!(optimised.analyse().dead_rules.is_empty());
+
+        let (filter, _) = read_input(indoc! {"
+            in{x>10:a,x>20:R,m<1:R,b}
+            a{s<100:b,A}
+            b{m>5:a,R}
+            c{A}
+
+        "});
+        assert_eq!(
+            indoc! {"
+                in{x>10:a,b}
+                a{s<100:b,A}
+                b{m>5:a,R}
+            "},
+            filter.optimise().to_string()
+        );
+    }
+
+    #[test]
+    fn optimise_puzzle_input_works() {
+        let (filter, parts) = read_input(include_str!("../resources/day19_workflows_parts.txt"));
+        let optimised = filter.optimise();
+        assert!(optimised.rules_count() < filter.rules_count());
+        assert_eq!(
+            filter.accepted_combinations(),
+            optimised.accepted_combinations()
+        );
+        for p in &parts {
+            assert_eq!(filter.accept(p), optimised.accept(p));
+        }
+    }
 }
@@ -1,5 +1,6 @@
 use itertools::Itertools;
 use std::cmp::{max, min};
+use std::fmt::{Display, Formatter};
 use std::str::Lines;
 
 #[derive(Debug)]
@@ -9,20 +10,48 @@ struct Conversion {
     len: u64,
 }
 
+impl Display for Conversion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.dest, self.source, self.len)
+    }
+}
+
 #[derive(Debug, Default)]
 struct Converter {
+    from: String,
+    to: String,
     conversions: Vec<Conversion>,
 }
 
 impl Converter {
-    pub fn convert(&self, source: u64) -> u64 {
+    /// convert source, returning the conversion rule used (None if source fell through unmapped)
+    fn lookup(&self, source: u64) -> (u64, Option<&Conversion>) {
         for c in &self.conversions {
             if c.source <= source && source < (c.source + c.len) {
                 let offset = source - c.source;
-                return c.dest + offset;
+                return (c.dest + offset, Some(c));
             }
         }
-        source
+        (source, None)
+    }
+
+    pub fn convert(&self, source: u64) -> u64 {
+        self.lookup(source).0
+    }
+
+    /// all the sources converted to dest : several rules may lead to the same dest,
+    /// and dest itself is a source if no rule maps it
+    fn revert(&self, dest: u64) -> Vec<(u64, Option<&Conversion>)> {
+        let mut sources: Vec<(u64, Option<&Conversion>)> = self
+            .conversions
+            .iter()
+            .filter(|c| c.dest <= dest && dest < c.dest + c.len)
+            .map(|c| (c.source + dest - c.dest, Some(c)))
+            .collect();
+        if self.lookup(dest).1.is_none() {
+            sources.push((dest, None));
+        }
+        sources
     }
 
     pub fn convert_range(&self, source: u64, len: u64) -> Vec<(u64, u64)> {
@@ -77,8 +106,16 @@ impl Converter {
 }
 
 fn read_converter(lines: &mut Lines) -> Converter {
-    assert!(lines.next().unwrap().contains("map:"));
-    let mut converter: Converter = Default::default();
+    let (from, to) = lines
+        .next()
+        .and_then(|l| l.strip_suffix(" map:"))
+        .and_then(|name| name.split_once("-to-"))
+        .unwrap();
+    let mut converter = Converter {
+        from: from.to_string(),
+        to: to.to_string(),
+        ..Default::default()
+    };
     for l in lines.by_ref() {
         if l.is_empty() {
             break;
@@ -90,7 +127,7 @@ fn read_converter(lines: &mut Lines) -> Converter {
     converter
 }
 
-fn get_location(input: &str) -> u64 {
+fn read_almanac(input: &str) -> (Vec<u64>, Vec<Converter>) {
     let mut lines = input.lines();
     let seeds: Vec<u64> = lines
         .next()
@@ -99,66 +136,25 @@ fn get_location(input: &str) -> u64 {
         .unwrap();
     let _ = lines.next();
 
-    let seed_to_soil = read_converter(&mut lines);
-    let soil_to_fertilizer = read_converter(&mut lines);
-    let fertilizer_to_water = read_converter(&mut lines);
-    let water_to_light = read_converter(&mut lines);
-    let light_to_temperature = read_converter(&mut lines);
-    let temperature_to_humidity = read_converter(&mut lines);
-    let humidity_to_location = read_converter(&mut lines);
+    (seeds, read_converters(&mut lines))
+}
+
+fn get_location(input: &str) -> u64 {
+    let (seeds, converters) = read_almanac(input);
 
     seeds
         .iter()
-        .map(|s| {
-            let mut result = *s;
-            for converter in [
-                &seed_to_soil,
-                &soil_to_fertilizer,
-                &fertilizer_to_water,
-                &water_to_light,
-                &light_to_temperature,
-                &temperature_to_humidity,
-                &humidity_to_location,
-            ] {
-                result = converter.convert(result);
-            }
-            result
-        })
+        .map(|s| converters.iter().fold(*s, |result, converter| converter.convert(result)))
         .min()
         .unwrap()
 }
 
 fn get_full_location(input: &str) -> u64 {
-    let mut lines = input.lines();
-    let mut seeds: Vec<(u64, u64)> = vec![];
-    let (_, l) = lines.next().unwrap().split_once(':').unwrap();
-    let mut vals = l.trim().split(' ');
-
-    while let Some(seed) = vals.next() {
-        if let Some(len) = vals.next() {
-            seeds.push((seed.parse().unwrap(), len.parse().unwrap()));
-        }
-    }
-    let _ = lines.next();
-
-    let seed_to_soil = read_converter(&mut lines);
-    let soil_to_fertilizer = read_converter(&mut lines);
-    let fertilizer_to_water = read_converter(&mut lines);
-    let water_to_light = read_converter(&mut lines);
-    let light_to_temperature = read_converter(&mut lines);
-    let temperature_to_humidity = read_converter(&mut lines);
-    let humidity_to_location = read_converter(&mut lines);
+    let (seeds, converters) = read_almanac(input);
+    let seeds: Vec<(u64, u64)> = seeds.into_iter().tuples().collect();
 
     let mut result = seeds;
-    for converter in [
-        &seed_to_soil,
-        &soil_to_fertilizer,
-        &fertilizer_to_water,
-        &water_to_light,
-        &light_to_temperature,
-        &temperature_to_humidity,
-        &humidity_to_location,
-    ] {
+    for converter in &converters {
         result = result
             .into_iter()
             .flat_map(|(s, l)| converter.convert_range(s, l).into_iter())
@@ -168,6 +164,106 @@ fn get_full_location(input: &str) -> u64 {
     result.into_iter().map(|(s, _)| s).min().unwrap()
 }
 
+/// one stage of a seed-to-location chain
+#[derive(Debug, Clone, Copy)]
+struct Step<'a> {
+    converter: &'a Converter,
+    source: u64,
+    dest: u64,
+    conversion: Option<&'a Conversion>,
+}
+
+impl Display for Step<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} -> {} {} ",
+            self.converter.from, self.source, self.converter.to, self.dest
+        )?;
+        match self.conversion {
+            Some(c) => write!(f, "(rule {c})"),
+            None => f.write_str("(unmapped)"),
+        }
+    }
+}
+
+fn read_converters(lines: &mut Lines) -> Vec<Converter> {
+    let mut converters = vec![];
+    while lines.clone().next().is_some() {
+        converters.push(read_converter(lines));
+    }
+    converters
+}
+
+fn explain_seed(converters: &[Converter], seed: u64) -> Vec<Step<'_>> {
+    let mut source = seed;
+    converters
+        .iter()
+        .map(|converter| {
+            let (dest, conversion) = converter.lookup(source);
+            let step = Step {
+                converter,
+                source,
+                dest,
+                conversion,
+            };
+            source = dest;
+            step
+        })
+        .collect()
+}
+
+/// every chain leading to location, ordered from seed to location
+fn explain_location(converters: &[Converter], location: u64) -> Vec<Vec<Step<'_>>> {
+    let mut chains: Vec<Vec<Step>> = vec![vec![]];
+    for converter in converters.iter().rev() {
+        chains = chains
+            .into_iter()
+            .flat_map(|chain| {
+                let dest = chain.last().map(|s: &Step| s.source).unwrap_or(location);
+                converter.revert(dest).into_iter().map(move |(source, conversion)| {
+                    let mut chain = chain.clone();
+                    chain.push(Step {
+                        converter,
+                        source,
+                        dest,
+                        conversion,
+                    });
+                    chain
+                })
+            })
+            .collect();
+    }
+    for chain in chains.iter_mut() {
+        chain.reverse();
+    }
+    chains
+}
+
+/// for each stage, the seeds that were not mapped by any rule
+fn unmapped_seeds<'a>(converters: &'a [Converter], seeds: &[u64]) -> Vec<(&'a str, Vec<u64>)> {
+    let chains: Vec<Vec<Step>> = seeds.iter().map(|s| explain_seed(converters, *s)).collect();
+    converters
+        .iter()
+        .enumerate()
+        .map(|(i, converter)| {
+            let unmapped = seeds
+                .iter()
+                .zip(&chains)
+                .filter(|(_, chain)| chain[i].conversion.is_none())
+                .map(|(seed, _)| *seed)
+                .collect();
+            (converter.from.as_str(), unmapped)
+        })
+        .collect()
+}
+
+fn print_chain(chain: &[Step]) {
+    for step in chain {
+        println!("  {step}");
+    }
+}
+
 pub fn process_seed() {
     let input = include_str!("../resources/day5_fertilizers.txt");
     let location = get_location(input);
@@ -175,6 +271,22 @@ pub fn process_seed() {
 
     let full_location = get_full_location(input);
     println!("full location {full_location}");
+
+    let (seeds, converters) = read_almanac(input);
+    let closest = seeds
+        .iter()
+        .map(|s| explain_seed(&converters, *s))
+        .min_by_key(|chain| chain.last().unwrap().dest)
+        .unwrap();
+    println!("closest seed chain :");
+    print_chain(&closest);
+    for (stage, unmapped) in unmapped_seeds(&converters, &seeds) {
+        println!("unmapped seeds at {stage} stage : {}", unmapped.len());
+    }
+    for (i, chain) in explain_location(&converters, full_location).iter().enumerate() {
+        println!("chain #{i} to full location {full_location} :");
+        print_chain(chain);
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -222,4 +334,68 @@ mod tests {
         assert_eq!(46, get_full_location(input));
         //137516820
     }
+
+    #[test]
+    fn explain_chains_works() {
+        let input = indoc! {"
+            seeds: 79 14 55 13
+
+            seed-to-soil map:
+            50 98 2
+            52 50 48
+
+            soil-to-fertilizer map:
+            0 15 37
+            37 52 2
+            39 0 15
+
+            fertilizer-to-water map:
+            49 53 8
+            0 11 42
+            42 0 7
+            57 7 4
+
+            water-to-light map:
+            88 18 7
+            18 25 70
+
+            light-to-temperature map:
+            45 77 23
+            81 45 19
+            68 64 13
+
+            temperature-to-humidity map:
+            0 69 1
+            1 0 69
+
+            humidity-to-location map:
+            60 56 37
+            56 93 4
+        "};
+        let (seeds, converters) = read_almanac(input);
+        assert_eq!(7, converters.len());
+        assert_eq!("humidity", converters[6].from);
+
+        let chain = explain_seed(&converters, 13);
+        assert_eq!(
+            vec![13, 13, 52, 41, 34, 34, 35, 35],
+            chain.iter().map(|s| s.source).chain([chain[6].dest]).collect_vec()
+        );
+        assert!(chain[0].conversion.is_none());
+        assert_eq!("seed 13 -> soil 13 (unmapped)", chain[0].to_string());
+        assert_eq!(
+            "soil 13 -> fertilizer 52 (rule 39 0 15)",
+            chain[1].to_string()
+        );
+
+        let chains = explain_location(&converters, 35);
+        assert!(chains.iter().any(|c| c[0].source == 13));
+        for chain in &chains {
+            assert_eq!(35, explain_seed(&converters, chain[0].source)[6].dest);
+        }
+
+        let unmapped = unmapped_seeds(&converters, &seeds);
+        assert_eq!(("seed", vec![14, 13]), unmapped[0]);
+        assert_eq!(("soil", vec![79, 55]), unmapped[1]);
+    }
 }
@@ -1,5 +1,5 @@
-use indoc::indoc;
 use itertools::Itertools;
+use num::{BigUint, One, Zero};
 
 /// distance parcourue :
 /// duréee totale : d
@@ -9,53 +9,67 @@ use itertools::Itertools;
 /// si on n'est pas bourrin on résoud  tacc²-T*tacc +Drace == 0, et on se place entre les racines
 /// delta = T²-4*Drace
 /// (T+sqrt(delta))/2 et (T-sqrt(delta))/2  nombre de valeur : sqrt(delta)
-
-fn compute_margins(time: u64, distance: u64) -> u64 {
-    // ⚠️ the f64 optimised version could be wrong for large values (because inner repr)
+///
+/// exact version : with u = 2*tacc - T, we want u² < delta with u of same parity as T
+/// (tacc must be an integer), so we count the u in [-m, m] with m the largest such value
+fn compute_margins(time: &BigUint, distance: &BigUint) -> BigUint {
     // brute force original :
     // (1..time).map(|tacc|time*tacc - tacc*tacc).filter(|d|*d > distance).count() as u64
+    let four_distance = distance * 4u32;
+    let square_time = time * time;
+    if square_time <= four_distance {
+        // the record can't be beaten
+        return BigUint::zero();
+    }
+    let delta = square_time - four_distance;
 
-    let delta: f64 = (time * time - 4 * distance) as f64;
-    assert!(delta > 0.0);
-    let delta_sqrt = delta.sqrt();
-    let ftime = time as f64;
-
-    let min_val = ((ftime - delta_sqrt) / 2.0).ceil() as u64;
-    let max_val = ((ftime + delta_sqrt) / 2.0).floor() as u64;
-
-    // if delta_sqrt is an int, equation have int solutions, hence range extremities distance match exacty the input distance
-    // thus must be put away
-    let exact_result = delta_sqrt == delta_sqrt.floor();
-    1 + max_val - min_val - if exact_result { 2 } else { 0 }
+    let mut max_u = delta.sqrt();
+    // if delta is a perfect square, the extremities exactly match the record distance, thus must be put away
+    if &max_u * &max_u == delta {
+        if max_u.is_zero() {
+            return BigUint::zero();
+        }
+        max_u -= 1u32;
+    }
+    if max_u.bit(0) != time.bit(0) {
+        if max_u.is_zero() {
+            return BigUint::zero();
+        }
+        max_u -= 1u32;
+    }
+    max_u + BigUint::one()
 }
 
-fn multiply_race_margins(input: &str) -> u64 {
+fn read_races(input: &str) -> (Vec<BigUint>, Vec<BigUint>) {
     let mut lines = input.lines().filter(|l| !l.is_empty());
     let (_, times) = lines.next().unwrap().split_once(':').unwrap();
-    let times: Vec<u64> = times.split(' ').filter_map(|w| w.parse().ok()).collect();
+    let times = times.split(' ').filter_map(|w| w.parse().ok()).collect();
 
     let (_, distances) = lines.next().unwrap().split_once(':').unwrap();
-    let distances: Vec<u64> = distances.split(' ').filter_map(|w| w.parse().ok()).collect();
+    let distances = distances.split(' ').filter_map(|w| w.parse().ok()).collect();
 
-    times.into_iter().zip(distances).map(|(t, d)| compute_margins(t, d)).product()
+    (times, distances)
 }
 
-fn compute_single_race_margins(input: &str) -> u64 {
+fn multiply_race_margins(input: &str) -> BigUint {
+    let (times, distances) = read_races(input);
+
+    times.iter().zip(&distances).map(|(t, d)| compute_margins(t, d)).product()
+}
+
+fn compute_single_race_margins(input: &str) -> BigUint {
     let mut lines = input.lines().filter(|l| !l.is_empty());
     let (_, times) = lines.next().unwrap().split_once(':').unwrap();
-    let time: u64 = times.split(' ').join("").parse().unwrap();
+    let time: BigUint = times.split(' ').join("").parse().unwrap();
 
     let (_, distances) = lines.next().unwrap().split_once(':').unwrap();
-    let distance: u64 = distances.split(' ').join("").parse().unwrap();
+    let distance: BigUint = distances.split(' ').join("").parse().unwrap();
 
-    compute_margins(time, distance)
+    compute_margins(&time, &distance)
 }
 
 pub fn race_boat() {
-    let input = indoc! {"
-        Time:        40     81     77     72
-        Distance:   219   1012   1365   1089
-    "};
+    let input = include_str!("../resources/day6_races.txt");
     let margins_product = multiply_race_margins(input);
     println!("margins product {margins_product}");
 
@@ -74,7 +88,33 @@ mod tests {
             Time:      7  15   30
             Distance:  9  40  200
         "};
-        assert_eq!(288, multiply_race_margins(input));
-        assert_eq!(71503, compute_single_race_margins(input));
+        assert_eq!(BigUint::from(288u32), multiply_race_margins(input));
+        assert_eq!(BigUint::from(71503u32), compute_single_race_margins(input));
+    }
+
+    #[test]
+    fn exact_margins_works() {
+        let brute_force = |time: u64, distance: u64| {
+            (1..time).filter(|tacc| tacc * (time - tacc) > distance).count()
+        };
+        for time in 0..60u64 {
+            for distance in 0..(time * time / 4 + 2) {
+                assert_eq!(
+                    BigUint::from(brute_force(time, distance)),
+                    compute_margins(&time.into(), &distance.into()),
+                    "time {time} distance {distance}"
+                );
+            }
+        }
+
+        // time² would overflow u64 here
+        let time: BigUint = "100000000000000000000001".parse().unwrap();
+        let distance: BigUint = "1000000000000000000000".parse().unwrap();
+        let margins = compute_margins(&time, &distance);
+        // margins should span almost the whole race
+        let tacc_min = (&time - &margins + 1u32) / 2u32;
+        assert!(&tacc_min * (&time - &tacc_min) > distance);
+        let tacc_out = &tacc_min - 1u32;
+        assert!(&tacc_out * (&time - &tacc_out) <= distance);
     }
 }
@@ -1,241 +1,282 @@
 use ahash::AHashMap;
+use eyre::{eyre, Error};
 use itertools::Itertools;
 use std::cmp::Ordering;
+use std::fmt::Write;
 
-// Fixme : derive PartialOrd instead
-#[repr(u8)]
-#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy)]
-enum Card {
-    A = 14,
-    K = 13,
-    Q = 12,
-    J = 11,
-    T = 10,
-    N9 = 9,
-    N8 = 8,
-    N7 = 7,
-    N6 = 6,
-    N5 = 5,
-    N4 = 4,
-    N3 = 3,
-    N2 = 2,
+/// hand types, from the counts of identical cards
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum CardType {
+    FiveK,
+    FourK,
+    FullHouse,
+    ThreeK,
+    TwoPairs,
+    OnePair,
+    HighCard,
+}
+
+impl CardType {
+    /// for hands larger than 5 cards, the largest group only needs to reach the kind size
+    fn from_counts(counts: &[usize]) -> Self {
+        let counts: Vec<usize> = counts.iter().copied().sorted().rev().collect();
+        let first = counts.first().copied().unwrap_or(0);
+        let second = counts.get(1).copied().unwrap_or(0);
+        match (first, second) {
+            (f, _) if f >= 5 => CardType::FiveK,
+            (4, _) => CardType::FourK,
+            (3, s) if s >= 2 => CardType::FullHouse,
+            (3, _) => CardType::ThreeK,
+            (2, 2) => CardType::TwoPairs,
+            (2, _) => CardType::OnePair,
+            _ => CardType::HighCard,
+        }
+    }
 }
-#[repr(u8)]
+
+/// how hands of the same type are ordered
 #[derive(Debug, Clone, Copy)]
-enum CardType {
-    FiveK = 6,
-    FourK = 5,
-    FullHouse = 4,
-    ThreeK = 3,
-    TwoPairs = 2,
-    OnePair = 1,
-    HighCard = 0,
+enum TieBreak {
+    /// first differing card, from left to right
+    FirstCard,
+    /// first differing card, once both hands are sorted from strongest to weakest card
+    HighestCard,
+}
+
+#[derive(Debug, Clone)]
+struct Ruleset {
+    /// cards from the weakest to the strongest
+    order: Vec<char>,
+    /// cards acting like whatever card makes the strongest hand type
+    wildcards: Vec<char>,
+    hand_size: usize,
+    /// hand types from the weakest to the strongest, absent types are weaker than any other
+    ranking: Vec<CardType>,
+    tie_break: TieBreak,
 }
+
+#[derive(Debug, Clone)]
 struct Hand {
-    cards: [Card; 5],
+    cards: String,
+    /// cards strength, according to the ruleset order
+    strengths: Vec<usize>,
     ctype: CardType,
+    /// card replacing the wildcards, if any
+    substitute: Option<char>,
 }
 
-fn to_card(card: char) -> Card {
-    match card {
-        'A' => Card::A,
-        'K' => Card::K,
-        'Q' => Card::Q,
-        'J' => Card::J,
-        'T' => Card::T,
-        '9' => Card::N9,
-        '8' => Card::N8,
-        '7' => Card::N7,
-        '6' => Card::N6,
-        '5' => Card::N5,
-        '4' => Card::N4,
-        '3' => Card::N3,
-        '2' => Card::N2,
-        _ => panic!("no card type {card}"),
-    }
-}
-fn to_cards(input: &str) -> [Card; 5] {
-    let mut result = [Card::N2; 5];
-    for (i, c) in input.trim().chars().take(5).enumerate() {
-        result[i] = to_card(c);
+impl Ruleset {
+    fn standard() -> Self {
+        Self {
+            order: "23456789TJQKA".chars().collect(),
+            wildcards: vec![],
+            hand_size: 5,
+            ranking: vec![
+                CardType::HighCard,
+                CardType::OnePair,
+                CardType::TwoPairs,
+                CardType::ThreeK,
+                CardType::FullHouse,
+                CardType::FourK,
+                CardType::FiveK,
+            ],
+            tie_break: TieBreak::FirstCard,
+        }
     }
-    result
-}
-fn get_type(cards: &[Card; 5]) -> CardType {
-    let mut counts: AHashMap<Card, usize> = AHashMap::new();
-    for c in cards {
-        *counts.entry(*c).or_default() += 1;
+
+    fn with_jokers() -> Self {
+        Self {
+            order: "J23456789TQKA".chars().collect(),
+            wildcards: vec!['J'],
+            ..Self::standard()
+        }
     }
-    let values: Vec<_> = counts.values().copied().collect();
-    if *values.iter().max().unwrap() == 5 {
-        return CardType::FiveK;
+
+    fn strength(&self, card: char) -> Result<usize, Error> {
+        self.order.iter().position(|c| *c == card).ok_or_else(|| eyre!("no card type {card}"))
     }
-    if *values.iter().max().unwrap() == 4 {
-        return CardType::FourK;
+
+    fn rank(&self, ctype: CardType) -> Option<usize> {
+        self.ranking.iter().position(|t| *t == ctype)
     }
-    if *values.iter().max().unwrap() == 3 {
-        for v in &values {
-            if *v == 2 {
-                return CardType::FullHouse;
+
+    /// wildcards all stand for the same card : either one of the other cards of the hand,
+    /// or the strongest card not in it
+    fn get_type(&self, cards: &str) -> (CardType, Option<char>) {
+        let mut counts: AHashMap<char, usize> = AHashMap::new();
+        let mut wildcards = 0;
+        for c in cards.chars() {
+            if self.wildcards.contains(&c) {
+                wildcards += 1;
+            } else {
+                *counts.entry(c).or_default() += 1;
             }
         }
-        return CardType::ThreeK;
-    }
-    match values.iter().filter(|v| **v == 2).count() {
-        2 => CardType::TwoPairs,
-        1 => CardType::OnePair,
-        _ => CardType::HighCard,
+        if wildcards == 0 {
+            return (
+                CardType::from_counts(&counts.values().copied().collect_vec()),
+                None,
+            );
+        }
+
+        let absent = self
+            .order
+            .iter()
+            .rev()
+            .find(|c| !self.wildcards.contains(c) && !counts.contains_key(c));
+        counts
+            .keys()
+            .chain(absent)
+            .map(|substitute| {
+                let substituted = counts
+                    .iter()
+                    .map(|(c, n)| if c == substitute { n + wildcards } else { *n })
+                    .chain((!counts.contains_key(substitute)).then_some(wildcards))
+                    .collect_vec();
+                (CardType::from_counts(&substituted), Some(*substitute))
+            })
+            .max_by_key(|(ctype, substitute)| {
+                (
+                    self.rank(*ctype),
+                    substitute.and_then(|s| self.strength(s).ok()),
+                )
+            })
+            .unwrap_or((CardType::from_counts(&[wildcards]), None))
     }
-}
 
-fn get_type_with_jokers(cards: &[Card; 5]) -> CardType {
-    let mut counts: AHashMap<Card, usize> = AHashMap::new();
-    for c in cards {
-        *counts.entry(*c).or_default() += 1;
+    fn parse_hand(&self, cards: &str) -> Result<Hand, Error> {
+        let cards = cards.trim();
+        let strengths: Vec<usize> = cards.chars().map(|c| self.strength(c)).try_collect()?;
+        if strengths.len() != self.hand_size {
+            return Err(eyre!("{cards} should hold {} cards", self.hand_size));
+        }
+        let (ctype, substitute) = self.get_type(cards);
+        Ok(Hand {
+            cards: cards.to_string(),
+            strengths,
+            ctype,
+            substitute,
+        })
     }
-    let values: Vec<_> = counts.values().copied().collect();
-    if *values.iter().max().unwrap() == 5 {
-        return CardType::FiveK;
+
+    fn cmp_hands(&self, h1: &Hand, h2: &Hand) -> Ordering {
+        self.rank(h1.ctype).cmp(&self.rank(h2.ctype)).then_with(|| match self.tie_break {
+            TieBreak::FirstCard => h1.strengths.cmp(&h2.strengths),
+            TieBreak::HighestCard => {
+                let sorted = |h: &Hand| h.strengths.iter().copied().sorted().rev().collect_vec();
+                sorted(h1).cmp(&sorted(h2))
+            }
+        })
     }
-    if *values.iter().max().unwrap() == 4 {
-        return if *counts.get(&Card::J).unwrap_or(&0) > 0 {
-            CardType::FiveK
-        } else {
-            CardType::FourK
-        };
+
+    /// hands with their bid, from the weakest to the strongest
+    fn rank_hands(&self, input: &str) -> Result<Vec<(Hand, u64)>, Error> {
+        let mut game: Vec<(Hand, u64)> = input
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(|l| {
+                let (cards, bid) =
+                    l.trim().split_once(' ').ok_or_else(|| eyre!("no bid in '{l}'"))?;
+                let bid: u64 = bid.parse().map_err(|e| eyre!("bid read error {e} in '{l}'"))?;
+                Ok::<_, Error>((self.parse_hand(cards)?, bid))
+            })
+            .try_collect()?;
+        game.sort_by(|(h1, _), (h2, _)| self.cmp_hands(h1, h2));
+        Ok(game)
     }
-    if *values.iter().max().unwrap() == 3 {
-        match *counts.get(&Card::J).unwrap_or(&0) {
-            2 => {
-                return CardType::FiveK;
-            }
-            1 => {
-                return CardType::FourK;
-            }
-            3 => {
-                // counting non J cards
-                return if counts.values().contains(&2) {
-                    CardType::FiveK
-                } else {
-                    CardType::FourK
-                };
-            }
-            _ => {
-                // 0 actually
-                for v in &values {
-                    if *v == 2 {
-                        return CardType::FullHouse;
-                    }
-                }
-                return CardType::ThreeK;
-            }
-        }
+
+    fn report(&self, input: &str) -> Result<Vec<RankedHand>, Error> {
+        Ok(self
+            .rank_hands(input)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, (hand, bid))| RankedHand {
+                hand,
+                bid,
+                rank: i as u64 + 1,
+            })
+            .collect())
     }
-    match values.iter().filter(|v| **v == 2).count() {
-        2 => match *counts.get(&Card::J).unwrap_or(&0) {
-            2 => CardType::FourK,
-            1 => CardType::FullHouse,
-            _ => CardType::TwoPairs,
-        },
-        1 => {
-            if counts.contains_key(&Card::J) {
-                // either there 2 J, and they'll bon ith any other card, or there one other pair with wich this J will bond
-                CardType::ThreeK
-            } else {
-                CardType::OnePair
-            }
-        }
 
-        _ => {
-            if counts.contains_key(&Card::J) {
-                CardType::OnePair
-            } else {
-                CardType::HighCard
-            }
-        }
+    fn sum_winnings(&self, input: &str) -> Result<u64, Error> {
+        Ok(self.report(input)?.iter().map(RankedHand::winnings).sum())
     }
 }
 
-fn sum_winnings(input: &str) -> u64 {
-    let game: Vec<(Hand, u64)> = input
-        .lines()
-        .filter(|l| !l.is_empty())
-        .map(|l| {
-            let (cards, bid) = l.trim().split_once(' ').unwrap();
-            let bid: u64 = bid.parse().unwrap();
-            let cards = to_cards(cards);
-            let ctype = get_type(&cards);
-            let hand = Hand { cards, ctype };
-            (hand, bid)
-        })
-        .sorted_by(
-            |(h1, _), (h2, _)| match (h1.ctype as isize).cmp(&(h2.ctype as isize)) {
-                Ordering::Equal => {
-                    for i in 0..5 {
-                        if (h1.cards[i] as isize).cmp(&(h2.cards[i] as isize)) != Ordering::Equal {
-                            return (h1.cards[i] as isize).cmp(&(h2.cards[i] as isize));
-                        }
-                    }
-                    Ordering::Equal
-                }
-                _ => (h1.ctype as isize).cmp(&(h2.ctype as isize)),
-            },
-        )
-        .collect();
-    game.into_iter().enumerate().map(|(i, (_, bid))| (i as u64 + 1) * bid).sum()
+#[derive(Debug)]
+struct RankedHand {
+    hand: Hand,
+    bid: u64,
+    rank: u64,
 }
 
-fn cmp_hands_with_j(h1: &Hand, h2: &Hand) -> std::cmp::Ordering {
-    match (h1.ctype as isize).cmp(&(h2.ctype as isize)) {
-        Ordering::Equal => {
-            for i in 0..5 {
-                let h1_value = if h1.cards[i] == Card::J {
-                    0
-                } else {
-                    h1.cards[i] as isize
-                };
-                let h2_value = if h2.cards[i] == Card::J {
-                    0
-                } else {
-                    h2.cards[i] as isize
-                };
-
-                if h1_value.cmp(&h2_value) != Ordering::Equal {
-                    return h1_value.cmp(&h2_value);
-                }
-            }
-            Ordering::Equal
-        }
-        _ => (h1.ctype as isize).cmp(&(h2.ctype as isize)),
+impl RankedHand {
+    fn winnings(&self) -> u64 {
+        self.rank * self.bid
     }
 }
 
-fn to_hand_with_j(cards: &str) -> Hand {
-    let cards = to_cards(cards);
-    let ctype = get_type_with_jokers(&cards);
-    Hand { cards, ctype }
+fn to_csv(report: &[RankedHand]) -> String {
+    let mut csv = String::from("cards,type,substitute,rank,bid,winnings\n");
+    for r in report {
+        let substitute = r.hand.substitute.map(String::from).unwrap_or_default();
+        let _ = writeln!(
+            csv,
+            "{},{:?},{substitute},{},{},{}",
+            r.hand.cards,
+            r.hand.ctype,
+            r.rank,
+            r.bid,
+            r.winnings()
+        );
+    }
+    csv
 }
-fn sum_winnings_with_j(input: &str) -> u64 {
-    let game: Vec<(Hand, u64)> = input
-        .lines()
-        .filter(|l| !l.is_empty())
-        .map(|l| {
-            let (cards, bid) = l.trim().split_once(' ').unwrap();
-            let bid: u64 = bid.parse().unwrap();
 
-            let hand = to_hand_with_j(cards);
-            (hand, bid)
+fn to_json(report: &[RankedHand]) -> String {
+    let hands = report
+        .iter()
+        .map(|r| {
+            let substitute =
+                r.hand.substitute.map(|c| format!("\"{c}\"")).unwrap_or("null".to_string());
+            format!(
+                "  {{\"cards\": \"{}\", \"type\": \"{:?}\", \"substitute\": {substitute}, \"rank\": {}, \"bid\": {}, \"winnings\": {}}}",
+                r.hand.cards,
+                r.hand.ctype,
+                r.rank,
+                r.bid,
+                r.winnings()
+            )
         })
-        .sorted_by(|(h1, _), (h2, _)| cmp_hands_with_j(h1, h2))
-        .collect();
-    game.into_iter().enumerate().map(|(i, (_, bid))| (i as u64 + 1) * bid).sum()
+        .join(",\n");
+    format!("[\n{hands}\n]\n")
 }
-pub fn play_camel_cards() {
+
+pub fn play_camel_cards(export: bool) {
     let input = include_str!("../resources/day7_camel_cards.txt");
-    let winnings = sum_winnings(input);
+    let winnings = Ruleset::standard().sum_winnings(input).unwrap();
     println!("winnings {winnings}");
-    let jwinnings = sum_winnings_with_j(input);
+    let jwinnings = Ruleset::with_jokers().sum_winnings(input).unwrap();
     println!("jwinnings {jwinnings}");
+
+    let jokers = Ruleset::with_jokers();
+    let report = jokers.report(input).unwrap();
+    let strongest = &report.last().unwrap().hand;
+    println!(
+        "strongest hand {} : {:?} with J as {:?}",
+        strongest.cards, strongest.ctype, strongest.substitute
+    );
+    if export {
+        std::fs::write(".day7_jokers_report.csv", to_csv(&report)).unwrap();
+        std::fs::write(".day7_jokers_report.json", to_json(&report)).unwrap();
+        println!("jokers report written to .day7_jokers_report.csv and .day7_jokers_report.json");
+    }
+    let highest_card_rules = Ruleset {
+        tie_break: TieBreak::HighestCard,
+        ..jokers
+    };
+    let hwinnings = highest_card_rules.sum_winnings(input).unwrap();
+    println!("jwinnings with highest card tie-break {hwinnings}");
 }
 
 #[cfg(test)]
@@ -252,24 +293,88 @@ mod tests {
             KTJJT 220
             QQQJA 483
         "};
-        let winnings = sum_winnings(input);
+        let winnings = Ruleset::standard().sum_winnings(input).unwrap();
         assert_eq!(6440, winnings);
-        assert_eq!(5905, sum_winnings_with_j(input));
-        let h1 = to_hand_with_j("JKKK2");
-        let h2 = to_hand_with_j("QQQQ2");
-        assert_eq!(Ordering::Less, cmp_hands_with_j(&h1, &h2));
+        let rules = Ruleset::with_jokers();
+        assert_eq!(5905, rules.sum_winnings(input).unwrap());
+        let h1 = rules.parse_hand("JKKK2").unwrap();
+        let h2 = rules.parse_hand("QQQQ2").unwrap();
+        assert_eq!(Ordering::Less, rules.cmp_hands(&h1, &h2));
 
         assert_eq!(
             Ordering::Equal,
-            cmp_hands_with_j(&to_hand_with_j("JKKK2"), &to_hand_with_j("JKKK2"))
+            rules.cmp_hands(
+                &rules.parse_hand("JKKK2").unwrap(),
+                &rules.parse_hand("JKKK2").unwrap()
+            )
         );
         assert_eq!(
             Ordering::Greater,
-            cmp_hands_with_j(&to_hand_with_j("JKKK2"), &to_hand_with_j("QKKK2"))
+            rules.cmp_hands(
+                &rules.parse_hand("JKKK2").unwrap(),
+                &rules.parse_hand("QKKK2").unwrap()
+            )
         );
 
-        let card = to_cards("A234J");
-        let _card_type = get_type_with_jokers(&card);
-        assert!(matches!(CardType::OnePair, _card_type));
+        assert_eq!(CardType::OnePair, rules.parse_hand("A234J").unwrap().ctype);
+        assert!(rules.parse_hand("A234X").is_err());
+    }
+
+    #[test]
+    fn custom_rulesets_works() {
+        // jokers and deuces are both wild, on 6 cards hands
+        let rules = Ruleset {
+            order: "2J3456789TQKA".chars().collect(),
+            wildcards: vec!['J', '2'],
+            hand_size: 6,
+            ..Ruleset::with_jokers()
+        };
+        assert!(rules.parse_hand("AKQJT").is_err());
+        let hand = rules.parse_hand("AKQJ23").unwrap();
+        assert_eq!((CardType::ThreeK, Some('A')), (hand.ctype, hand.substitute));
+        let hand = rules.parse_hand("JJ2222").unwrap();
+        assert_eq!((CardType::FiveK, Some('A')), (hand.ctype, hand.substitute));
+        let hand = rules.parse_hand("AAAKK3").unwrap();
+        assert_eq!((CardType::FullHouse, None), (hand.ctype, hand.substitute));
+
+        // poker like tie break
+        let rules = Ruleset {
+            tie_break: TieBreak::HighestCard,
+            ..Ruleset::standard()
+        };
+        let h1 = rules.parse_hand("2345A").unwrap();
+        let h2 = rules.parse_hand("KQJT9").unwrap();
+        assert_eq!(Ordering::Greater, rules.cmp_hands(&h1, &h2));
+        assert_eq!(Ordering::Less, Ruleset::standard().cmp_hands(&h1, &h2));
+    }
+
+    #[test]
+    fn report_works() {
+        let input = indoc! {"
+            32T3K 765
+            T55J5 684
+            KK677 28
+            KTJJT 220
+            QQQJA 483
+        "};
+        let report = Ruleset::with_jokers().report(input).unwrap();
+        assert_eq!(5905, report.iter().map(RankedHand::winnings).sum::<u64>());
+
+        assert_eq!(
+            indoc! {"
+                cards,type,substitute,rank,bid,winnings
+                32T3K,OnePair,,1,765,765
+                KK677,TwoPairs,,2,28,56
+                T55J5,FourK,5,3,684,2052
+                QQQJA,FourK,Q,4,483,1932
+                KTJJT,FourK,T,5,220,1100
+            "},
+            to_csv(&report)
+        );
+        let json = to_json(&report);
+        assert!(json.starts_with(
+            "[\n  {\"cards\": \"32T3K\", \"type\": \"OnePair\", \"substitute\": null, \"rank\": 1,"
+        ));
+        assert!(json.contains("{\"cards\": \"KTJJT\", \"type\": \"FourK\", \"substitute\": \"T\", \"rank\": 5, \"bid\": 220, \"winnings\": 1100}\n]"));
     }
 }
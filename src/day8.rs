@@ -1,6 +1,7 @@
 use ahash::AHashMap;
-use num::integer::lcm;
-use std::hash::Hash;
+use itertools::Itertools;
+use num::Integer;
+use std::fmt::Write;
 
 #[derive(Debug, Clone, Copy)]
 enum Direction {
@@ -11,15 +12,14 @@ enum Direction {
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 struct Node {
     name: &'static str,
-    left: &'static str,
-    right: &'static str,
+    left: usize,
+    right: usize,
 }
 
-// TODO : nodes could be sorted => nodes id, left and right could be stored as usize
-// starts and end indexes would be indexes too
+/// nodes are sorted by name, their id being their index
 struct Map {
     directions: Vec<Direction>,
-    nodes: AHashMap<&'static str, Node>,
+    nodes: Vec<Node>,
 }
 impl Map {
     pub fn new(input: &'static str) -> Self {
@@ -35,128 +35,240 @@ impl Map {
             })
             .collect();
 
-        let nodes: AHashMap<&'static str, Node> =
-            lines.filter(|l| !l.is_empty()).map(to_node).map(|n| (n.name, n)).collect();
+        let named_nodes: Vec<(&'static str, &'static str, &'static str)> =
+            lines.filter(|l| !l.is_empty()).map(to_node).sorted().collect();
+        let ids: AHashMap<&'static str, usize> =
+            named_nodes.iter().enumerate().map(|(id, (name, _, _))| (*name, id)).collect();
+        let nodes = named_nodes
+            .into_iter()
+            .map(|(name, left, right)| Node {
+                name,
+                left: ids[left],
+                right: ids[right],
+            })
+            .collect();
 
         Self { directions, nodes }
     }
+
+    fn id(&self, name: &str) -> Option<usize> {
+        self.nodes.binary_search_by_key(&name, |n| n.name).ok()
+    }
+
+    fn next(&self, id: usize, step: usize) -> usize {
+        let node = &self.nodes[id];
+        match self.directions[step % self.directions.len()] {
+            Direction::Left => node.left,
+            Direction::Right => node.right,
+        }
+    }
+
+    fn ghost_cycle(&self, start: usize) -> GhostCycle {
+        let dir_len = self.directions.len();
+        // first step at which each (node, direction index) state was reached
+        let mut visits: Vec<Option<usize>> = vec![None; self.nodes.len() * dir_len];
+        let mut path = vec![];
+        let mut current = start;
+        for step in 0usize.. {
+            let state = current * dir_len + step % dir_len;
+            if let Some(tail) = visits[state] {
+                let ends = path
+                    .iter()
+                    .positions(|id: &usize| self.nodes[*id].name.ends_with('Z'))
+                    .collect();
+                return GhostCycle {
+                    tail,
+                    period: step - tail,
+                    ends,
+                    path,
+                };
+            }
+            visits[state] = Some(step);
+            path.push(current);
+            current = self.next(current, step);
+        }
+        unreachable!()
+    }
+
+    fn ghost_cycles(&self) -> Vec<GhostCycle> {
+        (0..self.nodes.len())
+            .filter(|id| self.nodes[*id].name.ends_with('A'))
+            .map(|id| self.ghost_cycle(id))
+            .collect()
+    }
+
+    /// Graphviz export : start nodes are green, end nodes red,
+    /// and edges followed by a ghost cycle take this ghost color.
+    /// Names are quoted, as a DOT ID may not start with a digit
+    fn to_dot(&self, cycles: &[GhostCycle]) -> String {
+        const COLORS: [&str; 8] = [
+            "blue",
+            "orange",
+            "purple",
+            "cyan3",
+            "magenta",
+            "gold",
+            "brown",
+            "darkgreen",
+        ];
+        let dir_len = self.directions.len();
+        let mut cycle_edges: AHashMap<(usize, char), usize> = AHashMap::new();
+        for (ghost, c) in cycles.iter().enumerate() {
+            for step in c.tail..c.tail + c.period {
+                let dir = match self.directions[step % dir_len] {
+                    Direction::Left => 'L',
+                    Direction::Right => 'R',
+                };
+                cycle_edges.insert((c.path[step], dir), ghost);
+            }
+        }
+
+        let mut dot = String::from("digraph desert {\n");
+        for node in &self.nodes {
+            if node.name.ends_with('A') {
+                let _ = writeln!(dot, "  \"{}\" [style=filled, fillcolor=green];", node.name);
+            } else if node.name.ends_with('Z') {
+                let _ = writeln!(dot, "  \"{}\" [style=filled, fillcolor=red];", node.name);
+            }
+        }
+        for (id, node) in self.nodes.iter().enumerate() {
+            for (dir, next) in [('L', node.left), ('R', node.right)] {
+                let _ = write!(
+                    dot,
+                    "  \"{}\" -> \"{}\" [label={dir}",
+                    node.name, self.nodes[next].name
+                );
+                if let Some(ghost) = cycle_edges.get(&(id, dir)) {
+                    let _ = write!(dot, ", color={}, penwidth=2", COLORS[ghost % COLORS.len()]);
+                }
+                dot.push_str("];\n");
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
 }
 
-fn to_node(s: &'static str) -> Node {
+fn to_node(s: &'static str) -> (&'static str, &'static str, &'static str) {
     let (name, nexts) = s.split_once('=').unwrap();
     let name = name.trim();
     let (left, right) = nexts.split_once(',').unwrap();
     let (_, left) = left.trim().split_once('(').unwrap();
     let (right, _) = right.trim().split_once(')').unwrap();
 
-    Node { name, left, right }
+    (name, left, right)
 }
 
-fn path_len(input: &'static str) -> u64 {
-    let Map { directions, nodes } = Map::new(input);
-    let dir_len = directions.len();
-
-    let mut node = nodes.get("AAA").unwrap();
-    let mut len: u64 = 0;
-    while node.name != "ZZZ" {
-        node = match directions[len as usize % dir_len] {
-            Direction::Left => nodes.get(&node.left).unwrap(),
-            Direction::Right => nodes.get(&node.right).unwrap(),
+/// walk of a ghost from its start : it loops once a (node, direction index) state repeats
+#[derive(Debug, Clone)]
+struct GhostCycle {
+    /// steps before entering the cycle
+    tail: usize,
+    period: usize,
+    /// steps at which an end node is reached, up to the end of the first cycle
+    ends: Vec<usize>,
+    /// node ids reached at each step, up to the end of the first cycle
+    path: Vec<usize>,
+}
+
+impl GhostCycle {
+    fn is_end(&self, steps: usize) -> bool {
+        let steps = if steps < self.tail {
+            steps
+        } else {
+            self.tail + (steps - self.tail) % self.period
         };
-        len += 1;
+        self.ends.binary_search(&steps).is_ok()
+    }
+
+    fn cycle_ends(&self) -> impl Iterator<Item = usize> + '_ {
+        self.ends.iter().copied().filter(|e| *e >= self.tail)
     }
-    len
 }
 
-fn ghost_path_len(input: &'static str) -> u64 {
-    let Map { directions, nodes } = Map::new(input);
-    let dir_len = directions.len();
-
-    let start_nodes = nodes.iter().filter_map(|(_, n)| n.name.ends_with('A').then_some(n));
-
-    let revolving_paths: Vec<(Vec<&'static str>, usize)> = start_nodes
-        .map(|n| {
-            let mut current_node = n;
-            let mut steps_by_node: AHashMap<&'static str, Vec<usize>> = AHashMap::new();
-            let mut steps = vec![];
-            for len in 0usize.. {
-                let dir = directions[len % dir_len];
-                current_node = match dir {
-                    Direction::Left => nodes.get(&current_node.left).unwrap(),
-                    Direction::Right => nodes.get(&current_node.right).unwrap(),
-                };
-                steps.push(current_node.name);
+/// generalised CRT : x = a1 [m1] and x = a2 [m2] <=> x = a [lcm(m1,m2)], if it has a solution
+fn combine((a1, m1): (i128, i128), (a2, m2): (i128, i128)) -> Option<(i128, i128)> {
+    let gcd = m1.extended_gcd(&m2);
+    if (a2 - a1) % gcd.gcd != 0 {
+        return None;
+    }
+    let lcm = m1 / gcd.gcd * m2;
+    // m1*x = gcd [m2], thus a1 + m1*x*(a2-a1)/gcd = a2 [m2]
+    let k = ((a2 - a1) / gcd.gcd * gcd.x).rem_euclid(m2 / gcd.gcd);
+    Some(((a1 + m1 * k).rem_euclid(lcm), lcm))
+}
 
-                let lens = steps_by_node.entry(current_node.name).or_default();
-                if let Some(l) = lens.iter().find(|l| *l % dir_len == len % dir_len) {
-                    // println!("period found {} at {l} and {len} (mod {dir_len})",current_node.name);
-                    return (steps, len - l);
-                }
-                lens.push(len);
-            }
-            unreachable!()
-        })
-        .collect();
+fn path_len(input: &'static str) -> u64 {
+    let map = Map::new(input);
 
-    #[derive(Debug, Clone)]
-    struct Path {
-        end_indexes: Vec<usize>,
-        period: usize,
+    let mut node = map.id("AAA").unwrap();
+    let end = map.id("ZZZ").unwrap();
+    let mut len: usize = 0;
+    while node != end {
+        node = map.next(node, len);
+        len += 1;
     }
+    len as u64
+}
 
-    let paths: Vec<Path> = revolving_paths
-        .into_iter()
-        .map(|(steps, period)| {
-            let end_indexes = steps
-                .into_iter()
-                .enumerate()
-                .filter_map(|(i, n)| n.ends_with('Z').then_some(i))
-                .collect();
-            Path {
-                period,
-                end_indexes,
-            }
-        })
-        .collect();
+/// first step at which every ghost is on an end node
+fn ghosts_meeting(cycles: &[GhostCycle]) -> u64 {
+    // before the longest tail, ghosts can only meet on one of its ends
+    let longest_tail = cycles.iter().max_by_key(|c| c.tail).unwrap();
+    if let Some(steps) = longest_tail
+        .ends
+        .iter()
+        .take_while(|e| **e < longest_tail.tail)
+        .find(|e| cycles.iter().all(|c| c.is_end(**e)))
+    {
+        return *steps as u64;
+    }
 
-    // for each path, let's get the next point where there is an end match with the previous path
-    // from there, next match must be on the LCM(paths until current  periods), so we move to next path
-    // some path may have several end in their periods, so we must follow the road for each one of them
-    // we can stop anyway once we have gone farther than LCM(current  period, current path period) : there won't be a match ever, since we're on a periodic path, now
-    paths
+    // afterward, every ghost is cycling : we must solve steps = end [period] for each ghost,
+    // for each combination of their ends
+    let min_steps = longest_tail.tail as i128;
+    cycles
         .iter()
-        .fold(vec![(0, 1)], |acc, p| {
+        .fold(vec![(0, 1)], |acc, c| {
             acc.into_iter()
-                .flat_map(|(current, period)| {
-                    p.end_indexes.iter().filter_map(move |idx| {
-                        let next_period = lcm(period, p.period);
-                        let mut next_match = current;
-
-                        while next_match % p.period != idx % p.period {
-                            if next_match - current > idx + next_period {
-                                // there won't be any matching end on this path !
-                                return None;
-                            }
-                            next_match += period;
-                        }
-                        Some((next_match, next_period))
-                    })
-                })
+                .cartesian_product(c.cycle_ends().collect_vec())
+                .filter_map(|(acc, end)| combine(acc, (end as i128, c.period as i128)))
+                .unique()
                 .collect()
-        }) // Then, we can chose the shortest remaining one, and add the initial step
+        })
         .into_iter()
-        .map(|(len, _)| len + 1)
+        .map(|(a, m)| {
+            if a >= min_steps {
+                a
+            } else {
+                a + (min_steps - a + m - 1) / m * m
+            }
+        })
         .min()
-        .unwrap() as u64
+        .expect("ghosts never end up together") as u64
 }
 
-pub fn cross_desert() {
+pub fn cross_desert(export: bool) {
     let input = include_str!("../resources/day8_maps.txt");
     let len = path_len(input);
     println!("path len {len}");
-    let ghost_len = ghost_path_len(input);
+    let map = Map::new(input);
+    let cycles = map.ghost_cycles();
+    let ghost_len = ghosts_meeting(&cycles);
 
     println!("ghost len {ghost_len}");
+
+    for c in &cycles {
+        let start = map.nodes[c.path[0]].name;
+        println!(
+            "ghost {start} : tail {}, period {}, ends {:?}",
+            c.tail, c.period, c.ends
+        );
+    }
+    if export {
+        std::fs::write(".day8_network.dot", map.to_dot(&cycles)).unwrap();
+        println!("network written to .day8_network.dot");
+    }
 }
 
 #[cfg(test)]
@@ -188,6 +300,86 @@ mod tests {
             XXX = (XXX, XXX)
             "
         };
-        assert_eq!(6, ghost_path_len(input));
+        assert_eq!(6, ghosts_meeting(&Map::new(input).ghost_cycles()));
+    }
+
+    #[test]
+    fn ghost_cycles_works() {
+        // 11A enters its cycle after 2 steps, 22A has two ends in its cycle
+        let input = indoc! {"
+            L
+
+            11A = (11B, 11B)
+            11B = (11C, 11C)
+            11C = (11D, 11D)
+            11D = (11Z, 11Z)
+            11Z = (11C, 11C)
+            22A = (22Z, 22Z)
+            22Z = (22B, 22B)
+            22B = (2XZ, 2XZ)
+            2XZ = (22C, 22C)
+            22C = (22D, 22D)
+            22D = (22Z, 22Z)
+            33A = (33Z, 33Z)
+            33Z = (33A, 33A)
+            "
+        };
+        let map = Map::new(input);
+        let cycle = map.ghost_cycle(map.id("11A").unwrap());
+        assert_eq!(
+            (2, 3, vec![4]),
+            (cycle.tail, cycle.period, cycle.ends.clone())
+        );
+        assert!(cycle.is_end(7));
+        assert!(!cycle.is_end(8));
+        let cycle = map.ghost_cycle(map.id("22A").unwrap());
+        assert_eq!((1, 5, vec![1, 3]), (cycle.tail, cycle.period, cycle.ends));
+
+        // 11A ends on 4 + 3k, 22A on 1 + 5k or 3 + 5k, 33A on 1 + 2k
+        let brute_force = (0..)
+            .find(|s| {
+                ["11A", "22A", "33A"].iter().all(|n| {
+                    let mut id = map.id(n).unwrap();
+                    (0..*s).for_each(|step| id = map.next(id, step));
+                    map.nodes[id].name.ends_with('Z')
+                })
+            })
+            .unwrap();
+        assert_eq!(13, brute_force);
+        assert_eq!(13, ghosts_meeting(&Map::new(input).ghost_cycles()));
+
+        assert_eq!(Some((23, 30)), combine((3, 10), (8, 15)));
+        assert_eq!(None, combine((3, 10), (4, 15)));
+    }
+
+    #[test]
+    fn dot_export_works() {
+        let input = indoc! {"
+            LR
+
+            11A = (11B, XXX)
+            11B = (XXX, 11Z)
+            11Z = (11B, XXX)
+            22A = (22B, XXX)
+            22B = (22C, 22C)
+            22C = (22Z, 22Z)
+            22Z = (22B, 22B)
+            XXX = (XXX, XXX)
+            "
+        };
+        let map = Map::new(input);
+        let cycles = map.ghost_cycles();
+        assert_eq!(
+            vec!["11A", "11B", "11Z"],
+            cycles[0].path.iter().map(|id| map.nodes[*id].name).collect_vec()
+        );
+        let dot = map.to_dot(&cycles);
+        assert!(dot.starts_with("digraph desert {\n"));
+        assert!(dot.contains("  \"11A\" [style=filled, fillcolor=green];\n"));
+        assert!(dot.contains("  \"22Z\" [style=filled, fillcolor=red];\n"));
+        assert!(dot.contains("  \"11A\" -> \"11B\" [label=L];\n"));
+        assert!(dot.contains("  \"11B\" -> \"11Z\" [label=R, color=blue, penwidth=2];\n"));
+        assert!(dot.contains("  \"22Z\" -> \"22B\" [label=L, color=orange, penwidth=2];\n"));
+        assert!(dot.contains("  \"XXX\" -> \"XXX\" [label=R];\n"));
     }
 }
@@ -1,4 +1,6 @@
 use itertools::Itertools;
+use num::{BigInt, BigRational, ToPrimitive, Zero};
+
 fn build_history(vals: &[i64]) -> Vec<Vec<i64>> {
     let mut history: Vec<Vec<i64>> = vec![vals.into()];
     loop {
@@ -13,12 +15,83 @@ fn build_history(vals: &[i64]) -> Vec<Vec<i64>> {
     }
     history
 }
+
+/// minimal degree polynomial P fitting the observations : P(i) = vals[i]
+#[derive(Debug, Clone, PartialEq)]
+struct Polynomial {
+    /// newton form : P(x) = sum(diffs[k] * binomial(x, k)), diffs[k] being the first value of the k-th differences
+    diffs: Vec<BigInt>,
+    /// false if the polynomial needed every observation to fit :
+    /// then any sequence would have fitted, and nothing tells the data is polynomial
+    confirmed: bool,
+}
+
+impl Polynomial {
+    fn fit(vals: &[i64]) -> Self {
+        let history = build_history(vals);
+        let confirmed = history.last().is_some_and(|zeroes| !zeroes.is_empty());
+        let mut diffs: Vec<BigInt> =
+            history.iter().filter_map(|h| h.first()).map(|d| (*d).into()).collect();
+        while diffs.len() > 1 && diffs.last().is_some_and(|d| d.is_zero()) {
+            diffs.pop();
+        }
+
+        Self { diffs, confirmed }
+    }
+
+    fn degree(&self) -> usize {
+        self.diffs.len().saturating_sub(1)
+    }
+
+    /// usual coefficients, from the constant one to the highest degree one
+    fn coefs(&self) -> Vec<BigRational> {
+        // binomial(x, k) = x(x-1)..(x-k+1) / k!, everything is kept over degree! until the end
+        let degree = self.degree();
+        let factorial = |n: usize| (1..=n).fold(BigInt::from(1), |f, i| f * i);
+        let mut numerators = vec![BigInt::zero(); degree + 1];
+        let mut falling = vec![BigInt::from(1)];
+        for (k, diff) in self.diffs.iter().enumerate() {
+            let scale = diff * factorial(degree) / factorial(k);
+            for (n, f) in numerators.iter_mut().zip(&falling) {
+                *n += &scale * f;
+            }
+            // falling * (x - k)
+            let mut next = vec![BigInt::zero(); falling.len() + 1];
+            for (i, f) in falling.iter().enumerate() {
+                next[i + 1] += f;
+                next[i] -= f * k;
+            }
+            falling = next;
+        }
+        let denominator = factorial(degree);
+        numerators.into_iter().map(|n| BigRational::new(n, denominator.clone())).collect()
+    }
+
+    /// P(x) is an integer for any integer x
+    fn value_at(&self, x: i64) -> BigInt {
+        let mut binomial = BigInt::from(1);
+        let mut value = BigInt::zero();
+        for (k, diff) in self.diffs.iter().enumerate() {
+            value += diff * &binomial;
+            // binomial(x, k+1) = binomial(x, k) * (x-k) / (k+1), always an exact division
+            binomial = binomial * (x - k as i64) / (k + 1);
+        }
+        value
+    }
+}
+
+/// k-th value after the last observation, exact even far beyond i64
+fn extrapolate_next(vals: &[i64], k: usize) -> BigInt {
+    Polynomial::fit(vals).value_at((vals.len() - 1 + k) as i64)
+}
+
+/// k-th value before the first observation, exact even far beyond i64
+fn extrapolate_previous(vals: &[i64], k: usize) -> BigInt {
+    Polynomial::fit(vals).value_at(-(k as i64))
+}
+
 fn next_value(vals: &[i64]) -> i64 {
-    build_history(vals)
-        .iter()
-        .rev()
-        .map(|vals| vals.last().unwrap())
-        .fold(0, |prev, next| *next + prev)
+    extrapolate_next(vals, 1).to_i64().expect("next value overflows i64")
 }
 fn sum_next_values(current_values: &[Vec<i64>]) -> i64 {
     current_values
@@ -34,11 +107,7 @@ fn sum_next_values(current_values: &[Vec<i64>]) -> i64 {
 }
 
 fn previous_value(vals: &[i64]) -> i64 {
-    build_history(vals)
-        .iter()
-        .rev()
-        .map(|vals| vals.first().unwrap())
-        .fold(0, |prev, next| *next - prev)
+    extrapolate_previous(vals, 1).to_i64().expect("previous value overflows i64")
 }
 
 fn sum_previous_values(current_values: &[Vec<i64>]) -> i64 {
@@ -70,6 +139,17 @@ pub fn observe_oasis() {
 
     let prev_val = sum_previous_values(&current_values);
     println!("prev_val {prev_val}");
+
+    let polynomials = current_values.iter().map(|vals| Polynomial::fit(vals)).collect_vec();
+    let degrees = polynomials.iter().map(Polynomial::degree).counts();
+    println!("degrees {:?}", degrees.into_iter().sorted().collect_vec());
+    let unconfirmed = polynomials.iter().filter(|p| !p.confirmed).count();
+    println!("sequences not proven polynomial {unconfirmed}");
+    let highest = polynomials.iter().max_by_key(|p| p.degree()).unwrap();
+    println!(
+        "highest degree polynomial {:?}",
+        highest.coefs().iter().map(|c| c.to_string()).collect_vec()
+    );
 }
 #[cfg(test)]
 mod tests {
@@ -86,4 +166,50 @@ mod tests {
         assert_eq!(114, sum_next_values(&current_values));
         assert_eq!(2, sum_previous_values(&current_values));
     }
+
+    #[test]
+    fn polynomial_fit_works() {
+        let half = BigRational::new(1.into(), 2.into());
+        // n(n+1)/2
+        let triangular = Polynomial::fit(&[1, 3, 6, 10, 15, 21]);
+        assert_eq!(2, triangular.degree());
+        assert!(triangular.confirmed);
+        assert_eq!(
+            vec![
+                BigRational::from_integer(1.into()),
+                BigRational::new(3.into(), 2.into()),
+                half
+            ],
+            triangular.coefs()
+        );
+        assert_eq!(
+            BigInt::from(5050),
+            extrapolate_next(&[1, 3, 6, 10, 15, 21], 94)
+        );
+        assert_eq!(
+            BigInt::zero(),
+            extrapolate_previous(&[1, 3, 6, 10, 15, 21], 2)
+        );
+        assert_eq!(
+            BigInt::from(36),
+            extrapolate_previous(&[1, 3, 6, 10, 15, 21], 10)
+        );
+
+        let constant = Polynomial::fit(&[7, 7, 7]);
+        assert_eq!((0, true), (constant.degree(), constant.confirmed));
+        assert_eq!(BigInt::from(7), extrapolate_next(&[7, 7, 7], 1_000_000));
+        // n^3 goes past i64 for n > 2^21
+        let cubes = [0, 1, 8, 27, 64];
+        let n: i64 = 1 << 30;
+        assert_eq!(
+            BigInt::from(n).pow(3),
+            extrapolate_next(&cubes, n as usize - 4)
+        );
+        assert!(extrapolate_next(&cubes, n as usize - 4).to_i64().is_none());
+
+        // 3 points always fit a parabola
+        let guess = Polynomial::fit(&[1, 2, 4]);
+        assert_eq!((2, false), (guess.degree(), guess.confirmed));
+        assert!(!Polynomial::fit(&[1, 2, 4, 8, 16]).confirmed);
+    }
 }
@@ -23,6 +23,8 @@ mod day6;
 mod day7;
 mod day8;
 mod day9;
+#[cfg(test)]
+mod test_utils;
 
 use std::time::{Duration, Instant};
 
@@ -57,6 +59,14 @@ impl Timer {
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|a| a == "explain-parts") {
+        day19::explain_parts(&args[1..]);
+        return;
+    }
+    // reports and drawings are written to the current directory on demand only
+    let export = args.iter().any(|a| a == "--export");
+
     let mut timer = Timer::new();
 
     println!("*** day 1 *** ");
@@ -84,11 +94,11 @@ fn main() {
     timer.click();
 
     println!("*** day7 ***");
-    day7::play_camel_cards();
+    day7::play_camel_cards(export);
     timer.click();
 
     println!("*** day8 ***");
-    day8::cross_desert();
+    day8::cross_desert(export);
     timer.click();
 
     println!("*** day9 ***");
@@ -96,7 +106,7 @@ fn main() {
     timer.click();
 
     println!("*** day10 ***");
-    day10::follow_pipes();
+    day10::follow_pipes(export);
     timer.click();
 
     println!("*** day11 ***");
@@ -104,7 +114,6 @@ fn main() {
     timer.click();
 
     println!("*** day12 ***");
-    // takes ~ 1mn...
     day12::arrange_springs();
     timer.click();
 
@@ -121,7 +130,7 @@ fn main() {
     timer.click();
 
     println!("*** day16 ***");
-    day16::fix_contraption();
+    day16::fix_contraption(export);
     timer.click();
 
     println!("*** day17 ***");
@@ -129,11 +138,11 @@ fn main() {
     timer.click();
 
     println!("*** day18 ***");
-    day18::dig_lagoon();
+    day18::dig_lagoon(export);
     timer.click();
 
     println!("*** day19 ***");
-    day19::filter_parts();
+    day19::filter_parts(export);
     timer.click();
 
     println!("*** day20 ***");
@@ -0,0 +1,19 @@
+/// pseudo random grid, reproducible from its seed : each cell is built from the next state
+/// of a linear congruential generator, whose high bits are the most random
+pub fn random_grid<T>(
+    width: usize,
+    height: usize,
+    mut seed: u64,
+    cell: impl Fn(u64) -> T,
+) -> Vec<Vec<T>> {
+    (0..height)
+        .map(|_| {
+            (0..width)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    cell(seed)
+                })
+                .collect()
+        })
+        .collect()
+}